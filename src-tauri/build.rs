@@ -2,7 +2,6 @@ fn main() {
     // Check for required environment variables during build
     let required_env_vars = [
         "CLIENT_ID",
-        "CLIENT_SECRET",
         "REDIRECT_URI",
         "API"
     ];
@@ -14,5 +13,14 @@ fn main() {
         println!("cargo:rustc-env={}={}", var, std::env::var(var).unwrap());
     }
 
+    // The client secret is only baked in for dev builds, release builds read it at
+    // runtime (see Config::client_secret) so it never ships in the executable
+    if std::env::var("PROFILE").as_deref() == Ok("debug") {
+        if let Ok(secret) = std::env::var("CLIENT_SECRET") {
+            println!("cargo:rustc-env=CLIENT_SECRET={}", secret);
+        }
+    }
+    println!("cargo:rerun-if-env-changed=CLIENT_SECRET");
+
     tauri_build::build()
 }
@@ -1,11 +1,13 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fs;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{Mutex, Notify};
 use tauri::Emitter;
+use tauri_plugin_opener::OpenerExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct TokenData {
@@ -37,12 +39,708 @@ struct ApiData<T> {
     __next: Option<String>,
 }
 
+const TRANSACTION_LINES_ENDPOINT: &str = "bulk/Financial/TransactionLines";
+const TRANSACTION_FIELDS: &str = "AccountCode,AccountName,AmountDC,AmountFC,AmountVATBaseFC,AmountVATFC,AssetCode,AssetDescription,CostCenter,CostCenterDescription,CostUnit,CostUnitDescription,CreatorFullName,Currency,CustomField,Description,Division,Document,DocumentNumber,DocumentSubject,DueDate,EntryNumber,ExchangeRate,ExternalLinkDescription,ExternalLinkReference,ExtraDutyAmountFC,ExtraDutyPercentage,FinancialPeriod,FinancialYear,GLAccountCode,GLAccountDescription,InvoiceNumber,Item,ItemCode,ItemDescription,JournalCode,JournalDescription,LineType,Modified,ModifierFullName,Notes,OrderNumber,PaymentDiscountAmount,PaymentReference,Project,ProjectCode,ProjectDescription,Quantity,SerialNumber,ShopOrder,Status,Subscription,SubscriptionDescription,TrackingNumber,TrackingNumberDescription,Type,VATCode,VATCodeDescription,VATPercentage,VATType,YourRef";
+
+/// Numeric fields, parsed as numbers during fetch normalization and typed as
+/// numbers by the exports
+const AMOUNT_FIELDS: &[&str] = &[
+    "AmountDC",
+    "AmountFC",
+    "AmountVATBaseFC",
+    "AmountVATFC",
+    "ExchangeRate",
+    "ExtraDutyAmountFC",
+    "ExtraDutyPercentage",
+    "PaymentDiscountAmount",
+    "Quantity",
+    "VATPercentage",
+];
+
+/// Fields carrying Exact `/Date(ms)/` values
+const DATE_FIELDS: &[&str] = &[
+    "CancellationDate",
+    "Created",
+    "Date",
+    "DocumentDate",
+    "DueDate",
+    "EndDate",
+    "EntryDate",
+    "InvoiceDate",
+    "InvoicedTo",
+    "InvoicingStartDate",
+    "Modified",
+    "OrderDate",
+    "StartDate",
+];
+
+/// Counters and enums Exact returns as integers, restored as numbers on CSV import
+const INTEGER_FIELDS: &[&str] = &[
+    "Division",
+    "EntryNumber",
+    "FinancialPeriod",
+    "FinancialYear",
+    "InvoiceNumber",
+    "LineType",
+    "OrderNumber",
+    "Status",
+    "Type",
+];
+
+/// Column holding `Transaction::source_division` in CSV exports
+const SOURCE_DIVISION_COLUMN: &str = "source_division";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FieldKind {
+    Amount,
+    Date,
+    Text,
+}
+
+fn field_kind(field: &str) -> FieldKind {
+    if AMOUNT_FIELDS.contains(&field) {
+        FieldKind::Amount
+    } else if DATE_FIELDS.contains(&field) {
+        FieldKind::Date
+    } else {
+        FieldKind::Text
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub data: HashMap<String, serde_json::Value>,
+    /// Division the row was fetched from, so multi-division results stay attributable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_division: Option<i32>,
+}
+
+/// Optional user settings read from `~/.exact_gui/config.toml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Config {
+    /// Overrides the default `exact_gui/<version> (tauri)` user agent, e.g. for white-label builds
+    user_agent: Option<String>,
+    /// How many divisions `get_transactions_multi` fetches at the same time
+    max_parallel_divisions: Option<usize>,
+    /// Idle connections kept open per host (default 8, enough for parallel division fetches)
+    pool_max_idle_per_host: Option<usize>,
+    /// Seconds an idle connection is kept alive before being closed (default 90)
+    pool_idle_timeout_secs: Option<u64>,
+    /// HTTP/2 is negotiated via ALPN when Exact offers it; set to false to force HTTP/1.1
+    http2: Option<bool>,
+    /// How often (ms) an in-flight request checks for cancellation (default 100)
+    cancel_check_interval_ms: Option<u64>,
+    /// How many rows are normalized between cancellation checks (default 500)
+    cancel_check_rows: Option<usize>,
+    /// Name of an environment variable holding the OAuth client secret
+    client_secret_env: Option<String>,
+    /// Path of a file whose (trimmed) contents are the OAuth client secret
+    client_secret_file: Option<PathBuf>,
+    /// Read the client secret from the OS keychain (service `exact_gui`, user `client_secret`)
+    client_secret_keychain: Option<bool>,
+    /// Where tokens are kept: `file` (default, `tokens.json`), `keychain`, or
+    /// `passphrase` for `tokens.enc`, unlocked each session with `unlock`
+    token_store: Option<String>,
+    /// Sort locale for names, e.g. `nl`; `binary` restores plain byte-wise ordering
+    locale: Option<String>,
+    /// Seconds `start_login_flow` waits for the browser callback (default 300)
+    login_timeout_secs: Option<u64>,
+    /// Minimum milliseconds between `transaction-progress` events (default 100)
+    progress_interval_ms: Option<u64>,
+    /// Convert every `/Date(ms)/`-looking string instead of only the known date fields
+    scan_all_dates: Option<bool>,
+    /// Interrupted file fetches untouched for longer than this are discarded (default 72)
+    resumable_max_age_hours: Option<i64>,
+    /// Extra headers sent with every data request, e.g. a gateway API key. They are
+    /// never sent to the token endpoint and may not replace `Authorization`.
+    extra_headers: Option<BTreeMap<String, String>>,
+    /// Division used to list divisions when `current/Me` doesn't report one
+    default_division: Option<i32>,
+    /// OAuth scopes requested at login; empty leaves it to the app registration
+    scopes: Option<Vec<String>>,
+    /// Description lookups (GL accounts, accounts) running at the same time (default 4)
+    enrichment_concurrency: Option<usize>,
+    /// Response decoding: unset replaces invalid UTF-8, `utf-8` rejects it, `latin1`
+    /// decodes as ISO-8859-1
+    encoding: Option<String>,
+    /// Extra attempts at `current/Me` after login before giving up on a division (default 2)
+    division_fetch_retries: Option<u32>,
+    /// Request gzip/deflate compressed responses on data calls (default true); set to
+    /// false when a proxy mangles compressed bodies
+    compression: Option<bool>,
+    /// Page size per endpoint, set through `set_endpoint_page_size`
+    page_sizes: Option<BTreeMap<String, u32>>,
+    /// Retries one operation may spend across all its requests before it fails (default 20)
+    retry_budget: Option<u32>,
+    /// Names from `BACKUP_ENDPOINTS` that `backup_division` captures (default all)
+    backup_endpoints: Option<Vec<String>>,
+    /// Retries of a fetch's `$count` request before progress goes without a total (default 2)
+    count_retries: Option<u32>,
+    /// Data requests allowed in flight at once across all operations (default 3)
+    max_concurrent_requests: Option<usize>,
+    /// `reject` (default) or `join` a fetch identical to one already running
+    duplicate_fetches: Option<String>,
+}
+
+/// Exact can answer 200 without an `error` key and still leave out the tokens; that
+/// is a failure, not a session with a missing access token
+fn tokens_from_response(response: &serde_json::Value) -> Result<(String, Option<String>), String> {
+    let field = |name: &str| {
+        response
+            .get(name)
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+    };
+    let access_token =
+        field("access_token").ok_or("Authentication error: the token response contained no access_token")?;
+    Ok((access_token, field("refresh_token")))
+}
+
+/// Seconds before expiry at which the access token is refreshed
+const TOKEN_REFRESH_MARGIN_SECS: i64 = 30;
+
+/// When to refresh, from the response's `expires_in` (Exact sends it as a string)
+/// minus a safety margin; 570s, just under Exact's usual 10 minutes, if it is missing
+fn refresh_at_for(token_response: &serde_json::Value, now: i64) -> i64 {
+    let lifetime = token_response
+        .get("expires_in")
+        .and_then(parse_count)
+        .filter(|secs| *secs > 0)
+        .map(|secs| (secs - TOKEN_REFRESH_MARGIN_SECS).max(secs / 2))
+        .unwrap_or(570);
+    now + lifetime
+}
+
+#[cfg(debug_assertions)]
+fn compiled_client_secret() -> Option<String> {
+    option_env!("CLIENT_SECRET").map(String::from)
+}
+
+#[cfg(not(debug_assertions))]
+fn compiled_client_secret() -> Option<String> {
+    None
+}
+
+impl Config {
+    fn load(data_dir: &std::path::Path) -> Result<Self, String> {
+        let config_file = data_dir.join("config.toml");
+        if !config_file.exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(&config_file)
+            .map_err(|e| format!("Failed to read config: {}", e))?;
+        toml::from_str(&content).map_err(|e| format!("Failed to parse config: {}", e))
+    }
+
+    /// Resolves the OAuth client secret at runtime. Precedence: `client_secret_env`,
+    /// then `client_secret_file`, then the OS keychain, and finally the value compiled
+    /// in at build time, which is only available in dev builds. The secret itself is
+    /// never logged.
+    fn client_secret(&self) -> Result<String, String> {
+        if let Some(var) = &self.client_secret_env {
+            if let Ok(secret) = std::env::var(var) {
+                if !secret.trim().is_empty() {
+                    return Ok(secret.trim().to_string());
+                }
+            }
+            eprintln!("[CONFIG] Client secret variable {} is not set", var);
+        }
+        if let Some(path) = &self.client_secret_file {
+            match fs::read_to_string(path) {
+                Ok(secret) if !secret.trim().is_empty() => return Ok(secret.trim().to_string()),
+                Ok(_) => eprintln!("[CONFIG] Client secret file {} is empty", path.display()),
+                Err(e) => eprintln!("[CONFIG] Failed to read client secret file {}: {}", path.display(), e),
+            }
+        }
+        if self.client_secret_keychain == Some(true) {
+            match keyring::Entry::new("exact_gui", "client_secret").and_then(|entry| entry.get_password()) {
+                Ok(secret) => return Ok(secret),
+                Err(e) => eprintln!("[CONFIG] Failed to read client secret from keychain: {}", e),
+            }
+        }
+        compiled_client_secret().ok_or_else(|| {
+            "No client secret configured. Set client_secret_env, client_secret_file or client_secret_keychain in config.toml".to_string()
+        })
+    }
+
+    fn save(&self, data_dir: &std::path::Path) -> Result<(), String> {
+        let content = toml::to_string_pretty(self).map_err(|e| format!("Failed to serialize config: {}", e))?;
+        fs::write(data_dir.join("config.toml"), content).map_err(|e| format!("Failed to save config: {}", e))
+    }
+
+    /// Copy of the config without anything pointing at secrets, safe to share
+    fn redacted(&self) -> Config {
+        Config {
+            client_secret_env: None,
+            client_secret_file: None,
+            client_secret_keychain: None,
+            // Gateway headers are usually credentials as well
+            extra_headers: None,
+            ..self.clone()
+        }
+    }
+
+    /// Overlays every setting that is present in `other` on top of this config
+    fn merged_with(&self, other: &Config) -> Result<Config, String> {
+        let mut base = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        let overlay = serde_json::to_value(other).map_err(|e| e.to_string())?;
+        if let (Some(base), Some(overlay)) = (base.as_object_mut(), overlay.as_object()) {
+            for (key, value) in overlay {
+                if !value.is_null() {
+                    base.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        serde_json::from_value(base).map_err(|e| format!("Failed to merge config: {}", e))
+    }
+
+    /// The configured page size of an endpoint, or Exact's own when none is set
+    fn page_size(&self, endpoint: &str) -> u32 {
+        self.page_sizes
+            .as_ref()
+            .and_then(|sizes| sizes.get(endpoint))
+            .copied()
+            .unwrap_or_else(|| default_page_size(endpoint))
+    }
+
+    fn extra_headers(&self) -> Result<reqwest::header::HeaderMap, String> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in self.extra_headers.iter().flatten() {
+            let header_name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid header name in extra_headers: {}", name))?;
+            if header_name == reqwest::header::AUTHORIZATION {
+                return Err("extra_headers may not override Authorization".to_string());
+            }
+            let header_value = reqwest::header::HeaderValue::from_str(value)
+                .map_err(|_| format!("Invalid value for header {} in extra_headers", name))?;
+            headers.insert(header_name, header_value);
+        }
+        Ok(headers)
+    }
+
+    fn user_agent(&self) -> String {
+        self.user_agent
+            .clone()
+            .unwrap_or_else(|| format!("exact_gui/{} (tauri)", env!("CARGO_PKG_VERSION")))
+    }
+}
+
+/// Persistence backend for the OAuth tokens
+trait TokenStore: Send + Sync {
+    fn load(&self) -> Result<Option<TokenData>, String>;
+    fn save(&self, tokens: &TokenData) -> Result<(), String>;
+    fn clear(&self) -> Result<(), String>;
+    /// Short name for diagnostics, e.g. `file`
+    fn name(&self) -> String;
+    /// The stored tokens as written, without parsing them
+    fn read_raw(&self) -> Result<Option<String>, String>;
+    /// Whether the store needs a passphrase before tokens can be read or written
+    fn locked(&self) -> bool {
+        false
+    }
+    fn unlock(&self, _passphrase: &str) -> Result<(), String> {
+        Err("The token store is not passphrase protected".to_string())
+    }
+}
+
+/// Result of `verify_token_store`
+#[derive(Debug, Serialize)]
+struct TokenStoreStatus {
+    store: String,
+    exists: bool,
+    parses: bool,
+    missing_fields: Vec<String>,
+    /// `None` when the store is healthy (or simply empty)
+    problem: Option<String>,
+}
+
+fn verify_store(store: &dyn TokenStore) -> TokenStoreStatus {
+    let mut status = TokenStoreStatus {
+        store: store.name(),
+        exists: false,
+        parses: false,
+        missing_fields: Vec::new(),
+        problem: None,
+    };
+    let content = match store.read_raw() {
+        Ok(Some(content)) => content,
+        Ok(None) => return status,
+        Err(e) => {
+            status.problem = Some(e);
+            return status;
+        }
+    };
+    status.exists = true;
+
+    let json = match serde_json::from_str::<serde_json::Value>(&content) {
+        Ok(serde_json::Value::Object(map)) => map,
+        Ok(_) => {
+            status.problem = Some("Stored tokens are not a JSON object".to_string());
+            return status;
+        }
+        Err(e) => {
+            status.problem = Some(format!("Stored tokens are corrupted ({} bytes): {}", content.len(), e));
+            return status;
+        }
+    };
+    status.parses = true;
+
+    let non_empty = |v: &serde_json::Value| v.as_str().is_some_and(|s| !s.is_empty());
+    let expected = [
+        ("access_token", json.get("access_token").is_some_and(non_empty)),
+        ("refresh_token", json.get("refresh_token").is_some_and(non_empty)),
+        ("refresh_at", json.get("refresh_at").is_some_and(|v| v.is_i64())),
+    ];
+    for (field, valid) in expected {
+        if !valid {
+            status.missing_fields.push(field.to_string());
+        }
+    }
+    if !status.missing_fields.is_empty() {
+        status.problem = Some(format!(
+            "Stored tokens are missing {}",
+            status.missing_fields.join(", ")
+        ));
+    }
+    status
+}
+
+/// Permission change made by `secure_data_dir`, modes in octal
+#[derive(Debug, Clone, Serialize)]
+struct PermissionFix {
+    path: String,
+    old_mode: String,
+    new_mode: String,
+}
+
+/// Files in the data directory that hold tokens
+const TOKEN_FILES: &[&str] = &["tokens.json", "tokens.enc"];
+
+/// Restricts `path` to `mode` if it grants anything beyond it. Returns the
+/// previous mode when it was changed, `None` when it was already private or
+/// doesn't exist.
+#[cfg(unix)]
+fn restrict_permissions(path: &std::path::Path, mode: u32) -> Result<Option<u32>, String> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read permissions of {}: {}", path.display(), e)),
+    };
+    let old_mode = metadata.permissions().mode() & 0o777;
+    if old_mode & !mode == 0 {
+        return Ok(None);
+    }
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("Failed to set permissions of {}: {}", path.display(), e))?;
+    Ok(Some(old_mode))
+}
+
+/// Files under the user profile are already private by default ACLs on Windows
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &std::path::Path, _mode: u32) -> Result<Option<u32>, String> {
+    Ok(None)
+}
+
+/// Makes the data directory user-only (0700) and the token files 0600, so other
+/// users on a shared machine can't read the tokens. Logs every change.
+fn secure_data_dir(data_dir: &std::path::Path) -> Result<Vec<PermissionFix>, String> {
+    let mut fixes = Vec::new();
+    let targets = std::iter::once((data_dir.to_path_buf(), 0o700))
+        .chain(TOKEN_FILES.iter().map(|name| (data_dir.join(name), 0o600)));
+    for (path, mode) in targets {
+        if let Some(old_mode) = restrict_permissions(&path, mode)? {
+            eprintln!(
+                "[PERMISSIONS] {} was {:o}, open to other users; set to {:o}",
+                path.display(),
+                old_mode,
+                mode
+            );
+            fixes.push(PermissionFix {
+                path: path.display().to_string(),
+                old_mode: format!("{:o}", old_mode),
+                new_mode: format!("{:o}", mode),
+            });
+        }
+    }
+    Ok(fixes)
+}
+
+/// Plain `tokens.json` in the data directory
+struct FileStore {
+    path: PathBuf,
+}
+
+impl TokenStore for FileStore {
+    fn load(&self) -> Result<Option<TokenData>, String> {
+        let Ok(content) = fs::read_to_string(&self.path) else {
+            return Ok(None);
+        };
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse tokens file: {}", e))
+    }
+
+    /// Written to a temp file and renamed over `tokens.json`, so a crash mid-write
+    /// leaves the previous tokens intact instead of a truncated file
+    fn save(&self, tokens: &TokenData) -> Result<(), String> {
+        let temp_path = self.path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(tokens).unwrap())
+            .map_err(|e| format!("Failed to save tokens: {}", e))?;
+        restrict_permissions(&temp_path, 0o600)?;
+        fs::rename(&temp_path, &self.path).map_err(|e| format!("Failed to save tokens: {}", e))
+    }
+
+    fn clear(&self) -> Result<(), String> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)
+                .map_err(|e| format!("Failed to delete tokens file: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("file ({})", self.path.display())
+    }
+
+    fn read_raw(&self) -> Result<Option<String>, String> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read tokens file: {}", e)),
+        }
+    }
+}
+
+/// Windows Credential Manager / macOS Keychain / Secret Service, via `keyring`
+struct OsKeychainStore {
+    entry: keyring::Entry,
+}
+
+impl TokenStore for OsKeychainStore {
+    fn load(&self) -> Result<Option<TokenData>, String> {
+        match self.entry.get_password() {
+            Ok(content) => serde_json::from_str(&content)
+                .map(Some)
+                .map_err(|e| format!("Failed to parse tokens from keychain: {}", e)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(format!("Failed to read tokens from keychain: {}", e)),
+        }
+    }
+
+    fn save(&self, tokens: &TokenData) -> Result<(), String> {
+        self.entry
+            .set_password(&serde_json::to_string(tokens).unwrap())
+            .map_err(|e| format!("Failed to save tokens to keychain: {}", e))
+    }
+
+    fn clear(&self) -> Result<(), String> {
+        match self.entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Failed to delete tokens from keychain: {}", e)),
+        }
+    }
+
+    fn name(&self) -> String {
+        "keychain".to_string()
+    }
+
+    fn read_raw(&self) -> Result<Option<String>, String> {
+        match self.entry.get_password() {
+            Ok(content) => Ok(Some(content)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(format!("Failed to read tokens from keychain: {}", e)),
+        }
+    }
+}
+
+/// Prefix of the error returned while the passphrase protected token store is locked
+const TOKENS_LOCKED_ERROR: &str = "TokensLocked: the stored tokens are encrypted, unlock them with the passphrase";
+
+/// On-disk format of `tokens.enc`, all fields base64
+#[derive(Serialize, Deserialize)]
+struct EncryptedTokens {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// `tokens.enc` in the data directory, encrypted with XChaCha20-Poly1305 under a
+/// key derived (Argon2id) from the passphrase given to `unlock`. The key is only
+/// held in memory, so a copied data directory doesn't expose the tokens.
+struct PassphraseStore {
+    path: PathBuf,
+    /// Salt and derived key, set by `unlock`
+    key: std::sync::Mutex<Option<(Vec<u8>, [u8; 32])>>,
+}
+
+fn derive_token_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], String> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Failed to derive token key: {}", e))?;
+    Ok(key)
+}
+
+impl PassphraseStore {
+    fn read_file(&self) -> Result<Option<EncryptedTokens>, String> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map(Some)
+                .map_err(|e| format!("Failed to parse encrypted tokens: {}", e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read tokens file: {}", e)),
+        }
+    }
+
+    fn decrypt(file: &EncryptedTokens, key: &[u8; 32]) -> Result<String, String> {
+        use base64::Engine;
+        use chacha20poly1305::aead::{Aead, KeyInit};
+        let engine = base64::engine::general_purpose::STANDARD;
+        let nonce = engine
+            .decode(&file.nonce)
+            .map_err(|e| format!("Failed to parse encrypted tokens: {}", e))?;
+        let ciphertext = engine
+            .decode(&file.ciphertext)
+            .map_err(|e| format!("Failed to parse encrypted tokens: {}", e))?;
+        if nonce.len() != 24 {
+            return Err("Failed to parse encrypted tokens: invalid nonce".to_string());
+        }
+        let cipher = chacha20poly1305::XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key));
+        let plaintext = cipher
+            .decrypt(chacha20poly1305::XNonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| "Wrong passphrase".to_string())?;
+        String::from_utf8(plaintext).map_err(|e| format!("Failed to parse encrypted tokens: {}", e))
+    }
+
+    fn decrypted(&self) -> Result<Option<String>, String> {
+        let Some(file) = self.read_file()? else {
+            return Ok(None);
+        };
+        let key = self.key.lock().unwrap();
+        let (_, key) = key.as_ref().ok_or(TOKENS_LOCKED_ERROR)?;
+        Self::decrypt(&file, key).map(Some)
+    }
+}
+
+impl TokenStore for PassphraseStore {
+    /// Reads nothing while locked, so startup doesn't fail before `unlock`
+    fn load(&self) -> Result<Option<TokenData>, String> {
+        if self.locked() {
+            return Ok(None);
+        }
+        match self.decrypted()? {
+            Some(content) => serde_json::from_str(&content)
+                .map(Some)
+                .map_err(|e| format!("Failed to parse tokens file: {}", e)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, tokens: &TokenData) -> Result<(), String> {
+        use base64::Engine;
+        use chacha20poly1305::aead::{Aead, KeyInit};
+        let engine = base64::engine::general_purpose::STANDARD;
+        let key = self.key.lock().unwrap();
+        let (salt, key) = key.as_ref().ok_or(TOKENS_LOCKED_ERROR)?;
+        let nonce: [u8; 24] = rand::random();
+        let cipher = chacha20poly1305::XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key));
+        let ciphertext = cipher
+            .encrypt(
+                chacha20poly1305::XNonce::from_slice(&nonce),
+                serde_json::to_string(tokens).unwrap().as_bytes(),
+            )
+            .map_err(|_| "Failed to save tokens: encryption failed".to_string())?;
+        let file = EncryptedTokens {
+            salt: engine.encode(salt),
+            nonce: engine.encode(nonce),
+            ciphertext: engine.encode(ciphertext),
+        };
+        let temp_path = self.path.with_extension("enc.tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(&file).unwrap())
+            .map_err(|e| format!("Failed to save tokens: {}", e))?;
+        restrict_permissions(&temp_path, 0o600)?;
+        fs::rename(&temp_path, &self.path).map_err(|e| format!("Failed to save tokens: {}", e))
+    }
+
+    fn clear(&self) -> Result<(), String> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)
+                .map_err(|e| format!("Failed to delete tokens file: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        format!("passphrase ({})", self.path.display())
+    }
+
+    fn read_raw(&self) -> Result<Option<String>, String> {
+        self.decrypted()
+    }
+
+    fn locked(&self) -> bool {
+        self.key.lock().unwrap().is_none()
+    }
+
+    /// Checks the passphrase against the stored tokens. Without stored tokens the
+    /// passphrase is taken as the new one and used from the next save on.
+    fn unlock(&self, passphrase: &str) -> Result<(), String> {
+        use base64::Engine;
+        if passphrase.is_empty() {
+            return Err("Passphrase must not be empty".to_string());
+        }
+        let file = self.read_file()?;
+        let salt = match &file {
+            Some(file) => base64::engine::general_purpose::STANDARD
+                .decode(&file.salt)
+                .map_err(|e| format!("Failed to parse encrypted tokens: {}", e))?,
+            None => rand::random::<[u8; 16]>().to_vec(),
+        };
+        let key = derive_token_key(passphrase, &salt)?;
+        if let Some(file) = &file {
+            Self::decrypt(file, &key)?;
+        }
+        *self.key.lock().unwrap() = Some((salt, key));
+        Ok(())
+    }
+}
+
+/// Picks the configured token store, falling back to the file store when the
+/// keychain can't be reached on this platform
+fn open_token_store(config: &Config, data_dir: &std::path::Path) -> Box<dyn TokenStore> {
+    let file_store = FileStore {
+        path: data_dir.join("tokens.json"),
+    };
+    if config.token_store.as_deref() == Some("passphrase") {
+        return Box::new(PassphraseStore {
+            path: data_dir.join("tokens.enc"),
+            key: std::sync::Mutex::new(None),
+        });
+    }
+    if config.token_store.as_deref() != Some("keychain") {
+        return Box::new(file_store);
+    }
+    match keyring::Entry::new("exact_gui", "tokens") {
+        Ok(entry) => match entry.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Box::new(OsKeychainStore { entry }),
+            Err(e) => {
+                eprintln!("[TOKENS] Keychain unavailable, using tokens.json: {}", e);
+                Box::new(file_store)
+            }
+        },
+        Err(e) => {
+            eprintln!("[TOKENS] Keychain unavailable, using tokens.json: {}", e);
+            Box::new(file_store)
+        }
+    }
 }
 
 struct AppState {
+    config: Config,
     api: String,
     client_id: String,
     client_secret: String,
@@ -52,6 +750,61 @@ struct AppState {
     refresh_at: i64,
     current_division: Option<i32>,
     data_dir: PathBuf,
+    recent_errors: VecDeque<ErrorRecord>,
+    /// Shared client for data calls so connections are pooled across requests
+    http_client: reqwest::Client,
+    token_store: Box<dyn TokenStore>,
+    /// Result of the most recent transaction fetch, for slicing without new API calls
+    cached_transactions: Vec<Transaction>,
+    /// When `cached_transactions` was fetched
+    cached_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// `get_vat_codes` results per division
+    vat_codes: HashMap<i32, Vec<VatCode>>,
+    /// Division descriptions from the last division listing
+    division_names: HashMap<i32, String>,
+    /// Login started by `get_auth_url` that hasn't been exchanged for tokens yet
+    pending_login: Option<PendingLogin>,
+    /// Cost centers and cost units per (division, endpoint)
+    code_lists: HashMap<(i32, String), Vec<CodeDescription>>,
+    /// `get_journals` results per division
+    journals: HashMap<i32, Vec<Journal>>,
+    /// Unfiltered `get_projects` results per division
+    projects: HashMap<i32, Vec<Project>>,
+    /// `get_gl_classifications` results per division
+    gl_classifications: HashMap<i32, Vec<serde_json::Value>>,
+    /// `get_payment_conditions` results per division
+    payment_conditions: HashMap<i32, Vec<PaymentCondition>>,
+    /// Custom field definitions per division, for `expand_custom_fields`
+    custom_fields: HashMap<i32, Vec<CustomFieldDefinition>>,
+    /// `get_users` results per division
+    users: HashMap<i32, Vec<ExactUser>>,
+    /// Whether the current token can query each division, from `get_division_access`
+    division_access: HashMap<i32, bool>,
+    /// Reference data responses revalidated with conditional requests
+    validators: ValidatorCache,
+    /// Outcome of the most recent token refresh
+    last_refresh: Option<RefreshInfo>,
+}
+
+const MAX_RECENT_ERRORS: usize = 50;
+
+/// When the tokens were last refreshed and whether the result made it to the store
+#[derive(Debug, Clone, Serialize)]
+struct RefreshInfo {
+    refreshed_at: String,
+    /// Exact sent a new refresh token, replacing the old one
+    rotated: bool,
+    /// The new tokens were saved and read back; if not, the next start is logged out
+    persisted: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ErrorRecord {
+    timestamp: String,
+    command: String,
+    kind: String,
+    message: String,
 }
 
 impl AppState {
@@ -59,19 +812,63 @@ impl AppState {
         // Use a local data directory in the user's home directory
         let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE"))
             .map_err(|_| "Failed to get home directory")?;
-        let data_dir = PathBuf::from(home).join(".exact_gui");
+        Self::with_data_dir(PathBuf::from(home).join(".exact_gui"))
+    }
+
+    fn with_data_dir(data_dir: PathBuf) -> Result<Self, String> {
         fs::create_dir_all(&data_dir).map_err(|e| format!("Failed to create data directory: {}", e))?;
+        // Not fatal: the app still works, it only stays as exposed as it was
+        if let Err(e) = secure_data_dir(&data_dir) {
+            eprintln!("[PERMISSIONS] {}", e);
+        }
+
+        let config = Config::load(&data_dir)?;
+        // A missing secret only matters once we talk to the token endpoint
+        let client_secret = config.client_secret().unwrap_or_else(|e| {
+            eprintln!("[CONFIG] {}", e);
+            String::new()
+        });
+        let http_client = build_data_client(&config)?;
+        let max_requests = config
+            .max_concurrent_requests
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS)
+            .max(1);
+        // Sized once; the first state of the process decides it
+        REQUEST_LIMITER.get_or_init(|| RequestLimiter {
+            permits: tokio::sync::Semaphore::new(max_requests),
+            max: max_requests,
+        });
+        let token_store = open_token_store(&config, &data_dir);
 
         let mut state = AppState {
+            config,
             api: env!("API").to_string(),
             client_id: env!("CLIENT_ID").to_string(),
-            client_secret: env!("CLIENT_SECRET").to_string(),
+            client_secret,
             redirect_uri: env!("REDIRECT_URI").to_string(),
             access_token: None,
             refresh_token: None,
             refresh_at: 0,
             current_division: None,
             data_dir,
+            recent_errors: VecDeque::new(),
+            http_client,
+            token_store,
+            cached_transactions: Vec::new(),
+            cached_at: None,
+            vat_codes: HashMap::new(),
+            division_names: HashMap::new(),
+            pending_login: None,
+            code_lists: HashMap::new(),
+            journals: HashMap::new(),
+            projects: HashMap::new(),
+            gl_classifications: HashMap::new(),
+            payment_conditions: HashMap::new(),
+            custom_fields: HashMap::new(),
+            users: HashMap::new(),
+            division_access: HashMap::new(),
+            validators: ValidatorCache::default(),
+            last_refresh: None,
         };
 
         state.load_tokens();
@@ -79,33 +876,118 @@ impl AppState {
     }
 
     fn load_tokens(&mut self) {
-        let tokens_file = self.data_dir.join("tokens.json");
-        if let Ok(content) = fs::read_to_string(&tokens_file) {
-            if let Ok(token_data) = serde_json::from_str::<TokenData>(&content) {
+        match self.token_store.load() {
+            Ok(Some(token_data)) => {
                 self.access_token = Some(token_data.access_token);
                 self.refresh_token = Some(token_data.refresh_token);
                 self.refresh_at = token_data.refresh_at;
                 self.current_division = token_data.current_division;
             }
+            Ok(None) => {}
+            Err(e) => {
+                // Surface it in get_recent_errors, otherwise the app just looks logged out
+                eprintln!("[TOKENS] {}", e);
+                self.record_error("load_tokens", &e);
+            }
+        }
+    }
+
+    /// A locked passphrase store is reported as such instead of looking logged out
+    fn missing_token_error(&self, error: &str) -> String {
+        if self.token_store.locked() {
+            TOKENS_LOCKED_ERROR.to_string()
+        } else {
+            error.to_string()
         }
     }
 
     fn save_tokens(&self) -> Result<(), String> {
-        let tokens_file = self.data_dir.join("tokens.json");
         let token_data = TokenData {
             access_token: self.access_token.clone().ok_or("No access token")?,
             refresh_token: self.refresh_token.clone().ok_or("No refresh token")?,
             refresh_at: self.refresh_at,
             current_division: self.current_division,
         };
-        fs::write(&tokens_file, serde_json::to_string_pretty(&token_data).unwrap())
-            .map_err(|e| format!("Failed to save tokens: {}", e))?;
+        self.token_store.save(&token_data)
+    }
+
+    fn load_saved_queries(&self) -> Result<Vec<SavedQuery>, String> {
+        let queries_file = self.data_dir.join("queries.json");
+        if !queries_file.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(&queries_file)
+            .map_err(|e| format!("Failed to read saved queries: {}", e))?;
+        serde_json::from_str(&content).map_err(|e| format!("Failed to parse saved queries: {}", e))
+    }
+
+    fn save_saved_queries(&self, queries: &[SavedQuery]) -> Result<(), String> {
+        let queries_file = self.data_dir.join("queries.json");
+        fs::write(&queries_file, serde_json::to_string_pretty(queries).unwrap())
+            .map_err(|e| format!("Failed to save queries: {}", e))?;
         Ok(())
     }
 
+    fn load_sync_cursors(&self) -> HashMap<String, i64> {
+        fs::read_to_string(self.data_dir.join("sync_cursors.json"))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_sync_cursor(&self, key: &str, timestamp: i64) -> Result<(), String> {
+        let mut cursors = self.load_sync_cursors();
+        cursors.insert(key.to_string(), timestamp);
+        fs::write(
+            self.data_dir.join("sync_cursors.json"),
+            serde_json::to_string_pretty(&cursors).unwrap(),
+        )
+        .map_err(|e| format!("Failed to save sync cursor: {}", e))
+    }
+
+    /// Every HTTP client starts from here so all requests carry our user agent
+    fn client_builder(&self) -> reqwest::ClientBuilder {
+        client_builder(&self.config)
+    }
+
+    fn record_error(&mut self, command: &str, message: &str) {
+        if self.recent_errors.len() >= MAX_RECENT_ERRORS {
+            self.recent_errors.pop_front();
+        }
+        self.recent_errors.push_back(ErrorRecord {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            command: command.to_string(),
+            kind: error_kind(message).to_string(),
+            message: self.redact(message),
+        });
+    }
+
+    /// Strips anything secret out of a message before it is stored or shown
+    fn redact(&self, message: &str) -> String {
+        let mut redacted = message.to_string();
+        let secrets = [
+            Some(&self.client_secret),
+            self.access_token.as_ref(),
+            self.refresh_token.as_ref(),
+        ];
+        for secret in secrets.into_iter().flatten() {
+            if !secret.is_empty() {
+                redacted = redacted.replace(secret.as_str(), "[REDACTED]");
+            }
+        }
+        static TOKEN_FIELDS: OnceLock<regex::Regex> = OnceLock::new();
+        static BEARER: OnceLock<regex::Regex> = OnceLock::new();
+        let token_fields = TOKEN_FIELDS.get_or_init(|| {
+            regex::Regex::new(r#""(access_token|refresh_token|client_secret|code)"\s*:\s*"[^"]*""#).unwrap()
+        });
+        let redacted = token_fields.replace_all(&redacted, "\"$1\":\"[REDACTED]\"");
+        let bearer = BEARER.get_or_init(|| regex::Regex::new(r"(?i)bearer\s+\S+").unwrap());
+        bearer.replace_all(&redacted, "Bearer [REDACTED]").to_string()
+    }
+
     async fn fetch_current_division(&mut self) -> Result<(), String> {
-        let path = "/v1/current/Me?$select=CurrentDivision";
-        let response = self.get(path).await?;
+        let path = global_path("current/Me?$select=CurrentDivision")?;
+        let response = self.get(&path).await?;
         
         eprintln!("[CURRENT/ME] Response: {}", serde_json::to_string_pretty(&response).unwrap_or_else(|_| "Failed to serialize".to_string()));
 
@@ -142,21 +1024,61 @@ impl AppState {
             }
         }
 
+        // Anywhere else in an unexpected envelope
+        if let Some(division) = find_current_division(&response) {
+            self.current_division = Some(division);
+            eprintln!("[CURRENT/ME] Found current division: {}", division);
+            return Ok(());
+        }
+
         Err("Could not find CurrentDivision in response".to_string())
     }
 
+    /// Division to address `system/Divisions` through. Every division listing needs a
+    /// division in its path and `current/Me` is the only endpoint without one, so when
+    /// that fails `default_division` from the config is used instead.
+    async fn discover_division(&mut self) -> Result<i32, String> {
+        if let Some(division) = self.current_division {
+            return Ok(division);
+        }
+        if let Err(e) = self.fetch_current_division().await {
+            eprintln!("[DIVISION] current/Me did not return a division: {}", e);
+        }
+        if let Some(division) = self.current_division {
+            return Ok(division);
+        }
+        if let Some(division) = self.config.default_division {
+            eprintln!("[DIVISION] Using default_division {} from config", division);
+            return Ok(division);
+        }
+        Err("No current division found. Please authenticate first or set default_division in config.toml".to_string())
+    }
+
     async fn refresh_token(&mut self) -> Result<(), String> {
+        // Without any tokens there is nothing to refresh, so fail before any network call
+        if self.access_token.is_none() && self.refresh_token.is_none() {
+            return Err(self.missing_token_error(NOT_AUTHENTICATED_ERROR));
+        }
         if self.refresh_at > chrono::Utc::now().timestamp() {
             return Ok(());
         }
 
-        let refresh_token = self.refresh_token.clone().ok_or("No refresh token")?;
+        let refresh_token = self
+            .refresh_token
+            .clone()
+            .ok_or_else(|| self.missing_token_error("No refresh token"))?;
 
-        let client = reqwest::Client::new();
+        let client = self
+            .client_builder()
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
         let mut params = HashMap::new();
         params.insert("grant_type", "refresh_token");
         params.insert("refresh_token", &refresh_token);
         params.insert("client_id", &self.client_id);
+        if self.client_secret.is_empty() {
+            return Err("No client secret configured".to_string());
+        }
         params.insert("client_secret", &self.client_secret);
 
         let response = client
@@ -173,71 +1095,573 @@ impl AppState {
             .map_err(|e| format!("Failed to read token response: {}", e))?;
 
         eprintln!("[OAUTH2/TOKEN REFRESH] Status: {}", status);
-        eprintln!("[OAUTH2/TOKEN REFRESH] Response body: {}", response_text);
 
         let token_response: serde_json::Value = serde_json::from_str(&response_text)
             .map_err(|e| format!("Failed to parse token response: {}", e))?;
 
-        eprintln!("[OAUTH2/TOKEN REFRESH] Parsed JSON: {}", serde_json::to_string_pretty(&token_response).unwrap_or_else(|_| "Failed to serialize".to_string()));
-
         if let Some(error) = token_response.get("error") {
             return Err(format!("Token refresh error: {}", error));
         }
 
-        self.access_token = token_response
-            .get("access_token")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        self.refresh_token = token_response
-            .get("refresh_token")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
-        self.refresh_at = chrono::Utc::now().timestamp() + 570;
+        let (access_token, new_refresh_token) = tokens_from_response(&token_response)?;
+        // Exact invalidated the old refresh token, so the new one is kept in memory
+        // even when saving it fails; the refresh only succeeds once it is on disk
+        self.access_token = Some(access_token);
+        let rotated = new_refresh_token.is_some();
+        // Keep the current refresh token if Exact didn't rotate it
+        if let Some(new_refresh_token) = new_refresh_token {
+            self.refresh_token = Some(new_refresh_token);
+        }
+        self.refresh_at = refresh_at_for(&token_response, chrono::Utc::now().timestamp());
 
-        self.save_tokens()?;
+        let persisted = self.persist_refresh_token();
+        self.last_refresh = Some(RefreshInfo {
+            refreshed_at: chrono::Utc::now().to_rfc3339(),
+            rotated,
+            persisted: persisted.is_ok(),
+            error: persisted.as_ref().err().cloned(),
+        });
+        match &persisted {
+            Ok(()) => eprintln!("[TOKENS] Refreshed (rotated: {}), saved", rotated),
+            Err(e) => eprintln!("[TOKENS] Refreshed (rotated: {}) but not saved: {}", rotated, e),
+        }
+        persisted
+    }
 
+    /// Saves the tokens, trying once more when the first write fails, and reads
+    /// them back to make sure the refresh token a restart will use is the new one
+    fn persist_refresh_token(&self) -> Result<(), String> {
+        if let Err(e) = self.save_tokens() {
+            eprintln!("[TOKENS] Saving refreshed tokens failed, retrying: {}", e);
+            self.save_tokens()?;
+        }
+        let stored = self.token_store.load()?.map(|tokens| tokens.refresh_token);
+        if stored != self.refresh_token {
+            return Err("Saved tokens don't hold the new refresh token".to_string());
+        }
         Ok(())
     }
 
-    async fn get(&self, path: &str) -> Result<serde_json::Value, String> {
-        let access_token = self.access_token.clone().ok_or("Not authenticated")?;
+    /// Snapshot of the credentials needed for data calls, so long fetches can run
+    /// without holding the global state lock
+    fn session(&self) -> Result<ApiSession, String> {
+        let access_token = self
+            .access_token
+            .clone()
+            .ok_or_else(|| self.missing_token_error(NOT_AUTHENTICATED_ERROR))?;
 
-        let client = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+        Ok(ApiSession {
+            api: self.api.clone(),
+            access_token,
+            client: self.http_client.clone(),
+            cancel_check_interval: std::time::Duration::from_millis(
+                self.config.cancel_check_interval_ms.unwrap_or(100).max(1),
+            ),
+            cancel_check_rows: self.config.cancel_check_rows.unwrap_or(500).max(1),
+            encoding: BodyEncoding::from_config(self.config.encoding.as_deref())?,
+            retry_budget: self.config.retry_budget.unwrap_or(20),
+            validators: self.validators.clone(),
+        })
+    }
 
-        let response = client
-            .get(format!("{}{}", self.api, path))
-            .header("Accept", "application/json")
-            .header("Authorization", format!("Bearer {}", access_token))
-            .send()
-            .await
-            .map_err(|e| format!("HTTP request failed: {}", e))?;
+    async fn get(&self, path: &str) -> Result<serde_json::Value, String> {
+        self.session()?.get(path).await
+    }
+}
 
-        let status = response.status();
-        let body = response
-            .text()
-            .await
-            .map_err(|e| format!("Failed to read response: {}", e))?;
+fn client_builder(config: &Config) -> reqwest::ClientBuilder {
+    reqwest::Client::builder().user_agent(config.user_agent())
+}
 
-        if !status.is_success() {
-            return Err(format!("API error ({}): {}", status, body));
-        }
+/// The pool settings only tune connection reuse; rate limiting is unaffected.
+/// Only data requests go through this client, so `extra_headers` stay away from
+/// the token endpoint.
+fn build_data_client(config: &Config) -> Result<reqwest::Client, String> {
+    let mut builder = client_builder(config)
+        .default_headers(config.extra_headers()?)
+        .danger_accept_invalid_certs(true)
+        .pool_max_idle_per_host(config.pool_max_idle_per_host.unwrap_or(8))
+        .pool_idle_timeout(std::time::Duration::from_secs(
+            config.pool_idle_timeout_secs.unwrap_or(90),
+        ))
+        .gzip(config.compression.unwrap_or(true))
+        .deflate(config.compression.unwrap_or(true));
+    if config.http2 == Some(false) {
+        builder = builder.http1_only();
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))
+}
 
-        let json: serde_json::Value = serde_json::from_str(&body)
-            .map_err(|e| format!("Failed to parse JSON: {}", e))?;
+/// How response bodies are turned into text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyEncoding {
+    /// UTF-8, replacing invalid bytes (with a logged warning) instead of failing
+    Lossy,
+    /// UTF-8, failing on invalid bytes
+    Strict,
+    /// ISO-8859-1, for proxies that re-encode responses
+    Latin1,
+}
 
-        if let Some(error) = json.get("error") {
-            return Err(format!("API error: {}", error));
+impl BodyEncoding {
+    fn from_config(encoding: Option<&str>) -> Result<Self, String> {
+        match encoding.map(|e| e.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("lossy") => Ok(BodyEncoding::Lossy),
+            Some("utf-8") | Some("utf8") => Ok(BodyEncoding::Strict),
+            Some("latin1") | Some("latin-1") | Some("iso-8859-1") => Ok(BodyEncoding::Latin1),
+            Some(other) => Err(format!("Unsupported encoding in config: {}", other)),
         }
+    }
+}
 
-        Ok(json)
+fn decode_body(bytes: &[u8], encoding: BodyEncoding, path: &str) -> Result<String, String> {
+    match encoding {
+        BodyEncoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+        BodyEncoding::Strict => String::from_utf8(bytes.to_vec())
+            .map_err(|e| format!("Failed to read response: invalid UTF-8 from {}: {}", path, e)),
+        BodyEncoding::Lossy => match std::str::from_utf8(bytes) {
+            Ok(text) => Ok(text.to_string()),
+            Err(e) => {
+                eprintln!(
+                    "[ENCODING] Invalid UTF-8 in response from {} at byte {}, replacing invalid bytes",
+                    path,
+                    e.valid_up_to()
+                );
+                Ok(String::from_utf8_lossy(bytes).into_owned())
+            }
+        },
     }
 }
 
-static APP_STATE: Mutex<Option<AppState>> = Mutex::const_new(None);
-static CANCELLATION_FLAG: Mutex<Option<Arc<AtomicBool>>> = Mutex::const_new(None);
+/// Prefix of the error returned when Exact answers 403 for a division's endpoints,
+/// followed by the division code
+const DIVISION_ACCESS_LOST_ERROR: &str = "DivisionAccessLost: no longer allowed to access division";
+
+/// Prefix of the error returned for a division that isn't in the division listing,
+/// followed by the division code
+const INVALID_DIVISION_ERROR: &str = "InvalidDivision: unknown division";
+
+/// Prefix of the error returned for a fetch identical to a running one, followed by
+/// the running operation's id
+const DUPLICATE_FETCH_ERROR: &str = "DuplicateFetch: an identical fetch is already running as";
+
+/// Prefix of the error returned for a 429, followed by the seconds to wait
+const RATE_LIMITED_ERROR: &str = "RateLimited: too many requests, retry after seconds:";
+
+/// Seconds until Exact accepts requests again, from `Retry-After` or the minutely
+/// rate limit reset (milliseconds since the epoch)
+fn retry_after_secs(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+    if let Some(secs) = header("Retry-After").and_then(|v| v.parse::<u64>().ok()) {
+        return Some(secs);
+    }
+    let reset_ms = header("X-RateLimit-Minutely-Reset").and_then(|v| v.parse::<i64>().ok())?;
+    let wait_ms = reset_ms - chrono::Utc::now().timestamp_millis();
+    Some((wait_ms.max(0) as u64).div_ceil(1000))
+}
+
+fn division_in_path(path: &str) -> Option<i32> {
+    static DIVISION_PATH: OnceLock<regex::Regex> = OnceLock::new();
+    DIVISION_PATH
+        .get_or_init(|| regex::Regex::new(r"/v1/(\d+)/").unwrap())
+        .captures(path)
+        .and_then(|captures| captures[1].parse().ok())
+}
+
+/// The division a `DIVISION_ACCESS_LOST_ERROR` is about
+fn lost_division(error: &str) -> Option<i32> {
+    error
+        .strip_prefix(DIVISION_ACCESS_LOST_ERROR)
+        .and_then(|rest| rest.trim().parse().ok())
+}
+
+/// Clears the current division once Exact revoked access to it, so the UI can
+/// prompt for a new selection instead of 403ing on every call
+async fn handle_division_access_lost(app: &tauri::AppHandle, operation_id: &str, error: &str) {
+    let Some(division) = lost_division(error) else {
+        return;
+    };
+    if let Ok(mut state_guard) = get_app_state().await {
+        if let Some(state) = state_guard.as_mut() {
+            state.division_access.insert(division, false);
+            if state.current_division == Some(division) {
+                state.current_division = None;
+                if let Err(e) = state.save_tokens() {
+                    eprintln!("[DIVISION] Failed to persist cleared division: {}", e);
+                }
+            }
+        }
+    }
+    let _ = app.emit("division-access-lost", serde_json::json!({
+        "operation_id": operation_id,
+        "division": division,
+        "message": "Access to this division was revoked, please select another division"
+    }));
+}
+
+const NOT_AUTHENTICATED_ERROR: &str = "NotAuthenticated: Not authenticated, please log in first";
+
+const LOGIN_TIMED_OUT_ERROR: &str = "LoginTimedOut: the browser login was not completed in time";
+
+const SESSION_EXPIRED_ERROR: &str =
+    "Session expired: Exact returned a login page instead of data, please log in again";
+
+#[derive(Clone)]
+struct ApiSession {
+    api: String,
+    access_token: String,
+    client: reqwest::Client,
+    cancel_check_interval: std::time::Duration,
+    cancel_check_rows: usize,
+    encoding: BodyEncoding,
+    retry_budget: u32,
+    validators: ValidatorCache,
+}
+
+/// A reference data response with the validators Exact sent for it
+#[derive(Debug, Clone)]
+struct ValidatedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: serde_json::Value,
+    /// Last time the response was stored or revalidated, for eviction
+    used: std::time::Instant,
+}
+
+/// Paths `ValidatorCache` keeps; per-code lookups each have their own path, so an
+/// unbounded cache would hold a response body for every code seen in a session
+const VALIDATOR_CACHE_CAPACITY: usize = 256;
+
+/// Responses of `get_conditional` by path, shared by every session until logout.
+/// Once full, the least recently used path makes room for a new one.
+#[derive(Clone, Default)]
+struct ValidatorCache(Arc<std::sync::Mutex<HashMap<String, ValidatedResponse>>>);
+
+impl ValidatorCache {
+    fn get(&self, path: &str) -> Option<ValidatedResponse> {
+        let mut responses = self.0.lock().unwrap();
+        let response = responses.get_mut(path)?;
+        response.used = std::time::Instant::now();
+        Some(response.clone())
+    }
+
+    fn insert(&self, path: String, response: ValidatedResponse) {
+        let mut responses = self.0.lock().unwrap();
+        if responses.len() >= VALIDATOR_CACHE_CAPACITY && !responses.contains_key(&path) {
+            let oldest = responses
+                .iter()
+                .min_by_key(|(_, response)| response.used)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                responses.remove(&oldest);
+            }
+        }
+        responses.insert(path, response);
+    }
+
+    fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl ApiSession {
+    /// Like `get`, but drops the in-flight request (aborting the download) as soon
+    /// as the cancellation flag is raised instead of waiting for the page to finish
+    async fn get_cancellable(&self, path: &str, cancel_flag: &AtomicBool) -> Result<serde_json::Value, String> {
+        self.cancellable(self.get(path), cancel_flag).await
+    }
+
+    async fn cancellable<T>(
+        &self,
+        request: impl std::future::Future<Output = Result<T, String>>,
+        cancel_flag: &AtomicBool,
+    ) -> Result<T, String> {
+        tokio::pin!(request);
+        let mut ticker = tokio::time::interval(self.cancel_check_interval);
+        loop {
+            tokio::select! {
+                result = &mut request => return result,
+                _ = ticker.tick() => check_cancelled(cancel_flag)?,
+            }
+        }
+    }
+
+    /// Reads one page and hands its rows to `on_row`, returning the `__next` link.
+    /// Large bodies, or bodies of unknown size (e.g. compressed), are parsed while
+    /// they download, so a huge page is never held as text and as a JSON tree at
+    /// once. Small responses, error statuses and Latin-1 decoding use `get`'s path.
+    async fn get_rows<F>(&self, path: &str, cancel_flag: &AtomicBool, on_row: F) -> Result<Option<String>, String>
+    where
+        F: FnMut(serde_json::Map<String, serde_json::Value>) -> Result<(), String> + Send,
+    {
+        self.cancellable(self.read_rows(path, on_row), cancel_flag).await
+    }
+
+    async fn read_rows<F>(&self, path: &str, mut on_row: F) -> Result<Option<String>, String>
+    where
+        F: FnMut(serde_json::Map<String, serde_json::Value>) -> Result<(), String> + Send,
+    {
+        let (mut response, slot) = self.send(path).await?;
+        let is_json = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.to_lowercase().contains("json"));
+        let small = matches!(response.content_length(), Some(len) if len <= STREAMING_THRESHOLD_BYTES);
+        if !response.status().is_success() || !is_json || small || self.encoding == BodyEncoding::Latin1 {
+            let page = extract_page(self.read_json(response, path).await?)?;
+            for result in page.results {
+                if let serde_json::Value::Object(map) = result {
+                    on_row(map)?;
+                }
+            }
+            return Ok(page.__next);
+        }
+
+        let (chunk_tx, chunk_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(16);
+        let (row_tx, mut row_rx) = tokio::sync::mpsc::channel(256);
+        // Dropping the receivers (cancellation, a failing row) stops both tasks
+        let download = tokio::spawn(async move {
+            // Released once the body is read to the end or the download is dropped
+            let _slot = slot;
+            loop {
+                match response.chunk().await {
+                    Ok(Some(chunk)) => {
+                        if chunk_tx.send(chunk.to_vec()).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Ok(None) => return Ok(()),
+                    Err(e) => return Err(format!("Failed to read response: {}", e)),
+                }
+            }
+        });
+        let parser = tokio::task::spawn_blocking(move || {
+            parse_page_stream(
+                ChunkReader {
+                    chunks: chunk_rx,
+                    current: Vec::new(),
+                    offset: 0,
+                },
+                row_tx,
+            )
+        });
+
+        while let Some(row) = row_rx.recv().await {
+            on_row(row)?;
+        }
+        let downloaded = download.await.map_err(|e| format!("Failed to read response: {}", e))?;
+        let parsed = parser.await.map_err(|e| format!("Failed to parse response: {}", e))?;
+        // A broken download also fails to parse; the network error is the real cause
+        downloaded?;
+        parsed
+    }
+
+    /// Paths are relative to the API base. A `__next` link on another host (e.g.
+    /// after a load balancer redirect) is kept absolute and followed as-is rather
+    /// than having the base prepended a second time. The bearer token goes with
+    /// it, so it must be https and pass `next_page_allowed`.
+    fn url_for(&self, path: &str) -> Result<String, String> {
+        if !path.starts_with("http://") && !path.starts_with("https://") {
+            return Ok(format!("{}{}", self.api, path));
+        }
+        let target = reqwest::Url::parse(path).map_err(|e| format!("Invalid next page URL: {}", e))?;
+        if target.scheme() != "https" {
+            return Err(format!("Refusing to follow insecure next page URL: {}", path));
+        }
+        let api = reqwest::Url::parse(&self.api).map_err(|e| format!("Invalid API URL: {}", e))?;
+        if !next_page_allowed(&api, &target) {
+            return Err(format!(
+                "Refusing to follow next page URL on {}",
+                target.origin().ascii_serialization()
+            ));
+        }
+        if target.origin() != api.origin() {
+            eprintln!("[API] Following next page on {}", target.origin().ascii_serialization());
+        }
+        Ok(path.to_string())
+    }
+
+    async fn send(&self, path: &str) -> Result<(reqwest::Response, RequestSlot), String> {
+        self.send_with(path, reqwest::header::HeaderMap::new()).await
+    }
+
+    /// The returned slot must be kept until the response body has been read
+    async fn send_with(
+        &self,
+        path: &str,
+        headers: reqwest::header::HeaderMap,
+    ) -> Result<(reqwest::Response, RequestSlot), String> {
+        let url = self.url_for(path)?;
+        let slot = request_slot().await?;
+        let response = self
+            .client
+            .get(url)
+            .header("Accept", "application/json")
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .headers(headers)
+            .send()
+            .await
+            .map_err(|e| format!("HTTP request failed: {}", e))?;
+        observe_limits(response.headers());
+        Ok((response, slot))
+    }
+
+    /// `get` for rarely changing reference data: repeats of a path send the ETag /
+    /// Last-Modified of the previous response, and a 304 answers from that response
+    /// without spending a full request's rate limit. Responses without validators
+    /// aren't kept, so servers that don't support conditionals get plain requests.
+    async fn get_conditional(&self, path: &str) -> Result<serde_json::Value, String> {
+        let cached = self.validators.get(path);
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(cached) = &cached {
+            let validators = [
+                (reqwest::header::IF_NONE_MATCH, &cached.etag),
+                (reqwest::header::IF_MODIFIED_SINCE, &cached.last_modified),
+            ];
+            for (name, value) in validators {
+                if let Some(value) = value.as_deref().and_then(|v| reqwest::header::HeaderValue::from_str(v).ok()) {
+                    headers.insert(name, value);
+                }
+            }
+        }
+        let (response, _slot) = self.send_with(path, headers).await?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            if let Some(cached) = cached {
+                return Ok(cached.body);
+            }
+        }
+
+        let header = |name: reqwest::header::HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        let body = self.read_json(response, path).await?;
+        if etag.is_some() || last_modified.is_some() {
+            self.validators.insert(
+                path.to_string(),
+                ValidatedResponse {
+                    etag,
+                    last_modified,
+                    body: body.clone(),
+                    used: std::time::Instant::now(),
+                },
+            );
+        }
+        Ok(body)
+    }
+
+    async fn get(&self, path: &str) -> Result<serde_json::Value, String> {
+        let (response, _slot) = self.send(path).await?;
+        self.read_json(response, path).await
+    }
+
+    async fn read_json(&self, response: reqwest::Response, path: &str) -> Result<serde_json::Value, String> {
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_lowercase();
+        let retry_after = retry_after_secs(response.headers());
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to read response: {}", e))?;
+        let body = decode_body(&bytes, self.encoding, path)?;
+
+        // A dead session is sometimes answered with the HTML login page and a 200
+        if content_type.contains("text/html") || body.trim_start().starts_with('<') {
+            return Err(SESSION_EXPIRED_ERROR.to_string());
+        }
+
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(format!("{} {}", RATE_LIMITED_ERROR, retry_after.unwrap_or(5)));
+        }
+
+        if status == reqwest::StatusCode::FORBIDDEN {
+            if let Some(division) = division_in_path(path) {
+                return Err(format!("{} {}", DIVISION_ACCESS_LOST_ERROR, division));
+            }
+        }
+
+        if !status.is_success() {
+            return Err(format!("API error ({}): {}", status, body));
+        }
+
+        let json: serde_json::Value = serde_json::from_str(&body)
+            .map_err(|e| format!("Failed to parse JSON: {}", e))?;
+
+        if let Some(error) = json.get("error") {
+            return Err(format!("API error: {}", error));
+        }
+
+        Ok(json)
+    }
+
+    /// Raw download of a file Exact links to, e.g. a document attachment. The bearer
+    /// token is only sent to the API's own origin.
+    async fn download(&self, url: &str) -> Result<Vec<u8>, String> {
+        let target = reqwest::Url::parse(url).map_err(|e| format!("Invalid download URL: {}", e))?;
+        let api = reqwest::Url::parse(&self.api).map_err(|e| format!("Invalid API URL: {}", e))?;
+        if target.origin() != api.origin() {
+            return Err(format!("Refusing to download from {}", target.origin().ascii_serialization()));
+        }
+
+        let _slot = request_slot().await?;
+        let response = self
+            .client
+            .get(target)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await
+            .map_err(|e| format!("HTTP request failed: {}", e))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("Download failed ({})", status));
+        }
+        response
+            .bytes()
+            .await
+            .map(|bytes| bytes.to_vec())
+            .map_err(|e| format!("Failed to read download: {}", e))
+    }
+}
+
+static APP_STATE: Mutex<Option<AppState>> = Mutex::const_new(None);
+static OPERATIONS: Mutex<BTreeMap<String, Operation>> = Mutex::const_new(BTreeMap::new());
+static NEXT_OPERATION_ID: AtomicU64 = AtomicU64::new(1);
+/// Labels given to running operations, so tooling can cancel them by name.
+/// Locked after `OPERATIONS` when both are needed.
+static OPERATION_LABELS: Mutex<BTreeMap<String, String>> = Mutex::const_new(BTreeMap::new());
+/// Ids of resumable fetches currently writing, so they aren't listed or resumed twice
+static ACTIVE_RESUMABLE_FETCHES: Mutex<BTreeSet<String>> = Mutex::const_new(BTreeSet::new());
+/// Running collection fetches by their serialized `FetchRequest`, so a repeated
+/// start (e.g. a double click) can be rejected or joined. Locked before `OPERATIONS`.
+static IN_FLIGHT_FETCHES: Mutex<BTreeMap<String, InFlightFetch>> = Mutex::const_new(BTreeMap::new());
+
+struct InFlightFetch {
+    operation_id: String,
+    /// Set to the fetch's result once it finishes, for fetches that joined it
+    result: tokio::sync::watch::Receiver<Option<Result<Vec<Transaction>, String>>>,
+}
+
+struct Operation {
+    cancel_flag: Arc<AtomicBool>,
+    finished: Arc<Notify>,
+    paused: Arc<AtomicBool>,
+    resumed: Arc<Notify>,
+    /// Retries spent so far, across every request of the operation
+    retries: Arc<AtomicU32>,
+}
 
 async fn get_app_state() -> Result<tokio::sync::MutexGuard<'static, Option<AppState>>, String> {
     let mut state = APP_STATE.lock().await;
@@ -247,25 +1671,352 @@ async fn get_app_state() -> Result<tokio::sync::MutexGuard<'static, Option<AppSt
     Ok(state)
 }
 
+/// Like `begin_operation`, also registering `label` as a name for the operation.
+/// Fails when another running operation already has that label.
+async fn begin_labeled_operation(label: Option<String>) -> Result<(String, Arc<AtomicBool>), String> {
+    let Some(label) = label.filter(|l| !l.trim().is_empty()) else {
+        return Ok(begin_operation().await);
+    };
+    let mut operations = OPERATIONS.lock().await;
+    let mut labels = OPERATION_LABELS.lock().await;
+    if labels.contains_key(&label) {
+        return Err(format!("An operation labelled {} is already running", label));
+    }
+    let (id, cancel_flag) = register_operation(&mut operations);
+    labels.insert(label, id.clone());
+    Ok((id, cancel_flag))
+}
+
+async fn begin_operation() -> (String, Arc<AtomicBool>) {
+    register_operation(&mut *OPERATIONS.lock().await)
+}
+
+fn register_operation(operations: &mut BTreeMap<String, Operation>) -> (String, Arc<AtomicBool>) {
+    let id = format!("op-{}", NEXT_OPERATION_ID.fetch_add(1, Ordering::Relaxed));
+    let cancel_flag = Arc::new(AtomicBool::new(false));
+    operations.insert(
+        id.clone(),
+        Operation {
+            cancel_flag: cancel_flag.clone(),
+            finished: Arc::new(Notify::new()),
+            paused: Arc::new(AtomicBool::new(false)),
+            resumed: Arc::new(Notify::new()),
+            retries: Arc::new(AtomicU32::new(0)),
+        },
+    );
+    (id, cancel_flag)
+}
+
+async fn finish_operation(id: &str) {
+    let mut operations = OPERATIONS.lock().await;
+    if let Some(operation) = operations.remove(id) {
+        // notify_one stores a permit, so a waiter that subscribes late still wakes up
+        operation.finished.notify_one();
+    }
+    OPERATION_LABELS.lock().await.retain(|_, operation_id| operation_id != id);
+}
+
+/// The id of a running operation given its id or its label
+async fn resolve_operation(target: &str) -> Result<String, String> {
+    if OPERATIONS.lock().await.contains_key(target) {
+        return Ok(target.to_string());
+    }
+    OPERATION_LABELS
+        .lock()
+        .await
+        .get(target)
+        .cloned()
+        .ok_or(format!("No running operation {}", target))
+}
+
+/// Holds a pagination loop between pages while its operation is paused. Rows
+/// fetched so far stay with the caller; cancellation still ends the wait.
+async fn wait_while_paused(
+    operation_id: &str,
+    cancel_flag: &AtomicBool,
+    poll_interval: std::time::Duration,
+) -> Result<(), String> {
+    let Some((paused, resumed)) = OPERATIONS
+        .lock()
+        .await
+        .get(operation_id)
+        .map(|operation| (operation.paused.clone(), operation.resumed.clone()))
+    else {
+        return Ok(());
+    };
+    let mut ticker = tokio::time::interval(poll_interval);
+    while paused.load(Ordering::Relaxed) {
+        tokio::select! {
+            _ = resumed.notified() => {}
+            _ = ticker.tick() => check_cancelled(cancel_flag)?,
+        }
+    }
+    Ok(())
+}
+
+/// Takes one retry from the operation's budget. Per-request retry limits alone
+/// multiply over a long fetch on a flaky connection; once the budget is spent the
+/// operation fails instead of retrying page after page.
+async fn spend_retry(operation_id: &str, budget: u32) -> Result<(), String> {
+    let Some(retries) = OPERATIONS
+        .lock()
+        .await
+        .get(operation_id)
+        .map(|operation| operation.retries.clone())
+    else {
+        return Ok(());
+    };
+    if retries.fetch_add(1, Ordering::Relaxed) >= budget {
+        return Err(format!("Retry budget exhausted: {} retries used by {}", budget, operation_id));
+    }
+    Ok(())
+}
+
+async fn retries_used(operation_id: &str) -> u32 {
+    OPERATIONS
+        .lock()
+        .await
+        .get(operation_id)
+        .map(|operation| operation.retries.load(Ordering::Relaxed))
+        .unwrap_or(0)
+}
+
+async fn set_paused(target: &str, paused: bool) -> Result<(), String> {
+    let operation_id = resolve_operation(target).await?;
+    let operations = OPERATIONS.lock().await;
+    let operation = operations
+        .get(&operation_id)
+        .ok_or(format!("No running operation {}", target))?;
+    operation.paused.store(paused, Ordering::Relaxed);
+    if !paused {
+        operation.resumed.notify_waiters();
+    }
+    Ok(())
+}
+
+/// Classifies an error message so the frontend can group recent problems
+fn error_kind(message: &str) -> &'static str {
+    if message.contains("cancelled") {
+        "cancelled"
+    } else if message.starts_with("LoginTimedOut") {
+        "timeout"
+    } else if message.starts_with("DivisionAccessLost") {
+        "division_access"
+    } else if message.starts_with("InvalidDivision") {
+        "invalid_division"
+    } else if message.starts_with("DuplicateFetch") {
+        "duplicate"
+    } else if message.starts_with("RateLimited") {
+        "rate_limited"
+    } else if message.starts_with("TokensLocked") || message.contains("Wrong passphrase") {
+        "locked"
+    } else if message.contains("Not authenticated")
+        || message.contains("No refresh token")
+        || message.contains("Authentication error")
+        || message.contains("Token refresh error")
+        || message.contains("authenticate first")
+        || message.contains("Session expired")
+    {
+        "auth"
+    } else if message.contains("HTTP request failed")
+        || message.contains("Failed to refresh token")
+        || message.contains("Failed to authenticate")
+        || message.contains("Failed to read")
+    {
+        "network"
+    } else if message.contains("Failed to parse") {
+        "parse"
+    } else if message.contains("API error") {
+        "api"
+    } else if message.contains("Failed to save")
+        || message.contains("Failed to delete")
+        || message.contains("Failed to create")
+    {
+        "io"
+    } else {
+        "other"
+    }
+}
+
+/// Stores a failed command result in the recent errors buffer and passes it through
+async fn record_errors<T>(command: &str, result: Result<T, String>) -> Result<T, String> {
+    if let Err(message) = &result {
+        if let Ok(mut state_guard) = get_app_state().await {
+            if let Some(state) = state_guard.as_mut() {
+                state.record_error(command, message);
+            }
+        }
+    }
+    result
+}
+
+fn check_cancelled(cancel_flag: &AtomicBool) -> Result<(), String> {
+    if cancel_flag.load(Ordering::Relaxed) {
+        return Err("Operation cancelled by user".to_string());
+    }
+    Ok(())
+}
+
 #[tauri::command]
 async fn get_auth_url() -> Result<String, String> {
-    let state = get_app_state().await?;
-    let state = state.as_ref().ok_or("State not initialized")?;
-    Ok(format!(
-        "{}/oauth2/auth?client_id={}&redirect_uri={}&response_type=code",
-        state.api, state.client_id, state.redirect_uri
-    ))
+    record_errors("get_auth_url", build_auth_url().await).await
+}
+
+/// `state` and PKCE verifier of the login started by the last `get_auth_url`
+struct PendingLogin {
+    state: String,
+    code_verifier: String,
+    /// The `start_login_flow` operation waiting for this login's callback
+    operation_id: Option<String>,
+}
+
+impl PendingLogin {
+    fn new() -> Self {
+        use rand::distributions::{Alphanumeric, DistString};
+        let mut rng = rand::thread_rng();
+        PendingLogin {
+            state: Alphanumeric.sample_string(&mut rng, 32),
+            code_verifier: Alphanumeric.sample_string(&mut rng, 64),
+            operation_id: None,
+        }
+    }
+
+    /// S256 code challenge (RFC 7636)
+    fn code_challenge(&self) -> String {
+        use base64::Engine;
+        use sha2::Digest;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(sha2::Sha256::digest(self.code_verifier.as_bytes()))
+    }
+}
+
+/// Every call starts a fresh login: a new `state` and PKCE verifier replace any
+/// left over from an abandoned attempt
+async fn build_auth_url() -> Result<String, String> {
+    let mut state = get_app_state().await?;
+    let state = state.as_mut().ok_or("State not initialized")?;
+    let pending = PendingLogin::new();
+    let mut url = format!(
+        "{}/oauth2/auth?client_id={}&redirect_uri={}&response_type=code&state={}&code_challenge={}&code_challenge_method=S256",
+        state.api,
+        state.client_id,
+        state.redirect_uri,
+        pending.state,
+        pending.code_challenge()
+    );
+    let scopes = state.config.scopes.clone().unwrap_or_default();
+    if !scopes.is_empty() {
+        // RFC 6749 scope tokens: printable ASCII except space, `"` and `\`
+        if let Some(scope) = scopes.iter().find(|scope| {
+            scope.is_empty() || !scope.chars().all(|c| matches!(c, '!' | '#'..='[' | ']'..='~'))
+        }) {
+            return Err(format!("Invalid OAuth scope in config: {:?}", scope));
+        }
+        url.push_str(&format!("&scope={}", urlencoding::encode(&scopes.join(" "))));
+    }
+    state.pending_login = Some(pending);
+    Ok(url)
+}
+
+/// Abandons the login in progress: a waiting `start_login_flow` is stopped and the
+/// pending `state` / PKCE verifier are dropped
+#[tauri::command]
+async fn cancel_login() -> Result<(), String> {
+    let pending = {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        state.pending_login.take()
+    };
+    if let Some(operation_id) = pending.and_then(|pending| pending.operation_id) {
+        if let Some(operation) = OPERATIONS.lock().await.get(&operation_id) {
+            operation.cancel_flag.store(true, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn authenticate_with_code(code: String) -> Result<AuthStatus, String> {
+    record_errors("authenticate_with_code", exchange_code(code).await).await
 }
 
+/// Takes the redirect URL a user pasted back after a manual browser login and
+/// returns the authorization code for `authenticate_with_code`. The URL must be
+/// the configured redirect URI, and when a login was started with `get_auth_url`
+/// its `state` must match.
 #[tauri::command]
-async fn authenticate_with_code(code: String) -> Result<(), String> {
+async fn extract_code_from_redirect(url: String) -> Result<String, String> {
+    let result = async {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        code_from_redirect(
+            &url,
+            &state.redirect_uri,
+            state.pending_login.as_ref().map(|pending| pending.state.as_str()),
+        )
+    }
+    .await;
+    record_errors("extract_code_from_redirect", result).await
+}
+
+fn code_from_redirect(url: &str, redirect_uri: &str, expected_state: Option<&str>) -> Result<String, String> {
+    let url = reqwest::Url::parse(url.trim()).map_err(|e| format!("Invalid redirect URL: {}", e))?;
+    let expected = reqwest::Url::parse(redirect_uri).map_err(|e| format!("Invalid redirect URI: {}", e))?;
+    let same_target = url.scheme() == expected.scheme()
+        && url.host_str() == expected.host_str()
+        && url.port_or_known_default() == expected.port_or_known_default()
+        && url.path().trim_end_matches('/') == expected.path().trim_end_matches('/');
+    if !same_target {
+        return Err(format!(
+            "Redirect URL doesn't match the configured redirect URI {}",
+            redirect_uri
+        ));
+    }
+
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    if let Some(error) = params.get("error") {
+        let description = params.get("error_description").unwrap_or(error);
+        return Err(format!("Authentication error: {}", description));
+    }
+    let code = params
+        .get("code")
+        .filter(|code| !code.trim().is_empty())
+        .ok_or("Redirect URL did not contain a code")?;
+    if let Some(expected_state) = expected_state {
+        if params.get("state").map(String::as_str) != Some(expected_state) {
+            return Err("Authentication error: login state mismatch, please start the login again".to_string());
+        }
+    }
+    Ok(code.clone())
+}
+
+/// Outcome of a successful login
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+enum AuthStatus {
+    Authenticated,
+    /// Logged in, but Exact didn't report a current division even after retrying;
+    /// the user has to pick one (or `default_division` is used for listing)
+    AuthenticatedNoDivision,
+}
+
+async fn exchange_code(code: String) -> Result<AuthStatus, String> {
     let mut state_guard = get_app_state().await?;
     let state = state_guard.as_mut().ok_or("State not initialized")?;
+    // An authorization code is single use, so the pending login ends here either way
+    let pending = state.pending_login.take();
 
-    let client = reqwest::Client::new();
+    let client = state
+        .client_builder()
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
     let mut params = HashMap::new();
     params.insert("grant_type", "authorization_code");
+    if let Some(pending) = &pending {
+        params.insert("code_verifier", &pending.code_verifier);
+    }
     params.insert("client_id", &state.client_id);
+    if state.client_secret.is_empty() {
+        return Err("No client secret configured".to_string());
+    }
     params.insert("client_secret", &state.client_secret);
     params.insert("redirect_uri", &state.redirect_uri);
     params.insert("code", &code);
@@ -284,290 +2035,5854 @@ async fn authenticate_with_code(code: String) -> Result<(), String> {
         .map_err(|e| format!("Failed to read token response: {}", e))?;
 
     eprintln!("[OAUTH2/TOKEN] Status: {}", status);
-    eprintln!("[OAUTH2/TOKEN] Response body: {}", response_text);
 
     let token_response: serde_json::Value = serde_json::from_str(&response_text)
         .map_err(|e| format!("Failed to parse token response: {}", e))?;
 
-    eprintln!("[OAUTH2/TOKEN] Parsed JSON: {}", serde_json::to_string_pretty(&token_response).unwrap_or_else(|_| "Failed to serialize".to_string()));
-
     if let Some(error) = token_response.get("error") {
         return Err(format!("Authentication error: {}", error));
     }
 
-    state.access_token = token_response
-        .get("access_token")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
-    state.refresh_token = token_response
-        .get("refresh_token")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
-    state.refresh_at = chrono::Utc::now().timestamp() + 570;
+    let (access_token, refresh_token) = tokens_from_response(&token_response)?;
+    let refresh_token =
+        refresh_token.ok_or("Authentication error: the token response contained no refresh_token")?;
+    state.access_token = Some(access_token);
+    state.refresh_token = Some(refresh_token);
+    state.refresh_at = refresh_at_for(&token_response, chrono::Utc::now().timestamp());
+
+    // Fetch and store the current division, retrying a transient failure a few times.
+    // Authentication itself still succeeds without it.
+    let retries = state.config.division_fetch_retries.unwrap_or(2);
+    let mut attempt = 0;
+    while let Err(e) = state.fetch_current_division().await {
+        eprintln!("[AUTH] Warning: Failed to fetch current division: {}", e);
+        if attempt >= retries {
+            break;
+        }
+        attempt += 1;
+        tokio::time::sleep(std::time::Duration::from_millis(500 * u64::from(attempt))).await;
+    }
+
+    state.save_tokens()?;
+
+    Ok(if state.current_division.is_some() {
+        AuthStatus::Authenticated
+    } else {
+        AuthStatus::AuthenticatedNoDivision
+    })
+}
+
+/// Logs in through the browser with a local listener on the (localhost) redirect
+/// URI catching the callback. The wait is bounded by `login_timeout_secs` and can be
+/// aborted with `cancel_operation`; either way the listener is shut down.
+#[tauri::command]
+async fn start_login_flow(app: tauri::AppHandle) -> Result<AuthStatus, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = run_login_flow(&app, &operation_id, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("start_login_flow", result).await
+}
+
+async fn run_login_flow(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    cancel_flag: &AtomicBool,
+) -> Result<AuthStatus, String> {
+    let auth_url = build_auth_url().await?;
+    let (redirect_uri, timeout, expected_state) = {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        let pending = state.pending_login.as_mut().ok_or("Login was cancelled")?;
+        pending.operation_id = Some(operation_id.to_string());
+        let expected_state = pending.state.clone();
+        (
+            state.redirect_uri.clone(),
+            std::time::Duration::from_secs(state.config.login_timeout_secs.unwrap_or(300)),
+            expected_state,
+        )
+    };
+
+    let redirect = reqwest::Url::parse(&redirect_uri).map_err(|e| format!("Invalid redirect URI: {}", e))?;
+    if !matches!(redirect.host_str(), Some("localhost") | Some("127.0.0.1")) {
+        return Err("The login flow needs a localhost redirect URI, paste the code instead".to_string());
+    }
+    let port = redirect.port_or_known_default().ok_or("Redirect URI has no port")?;
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .map_err(|e| format!("Failed to listen for the login callback: {}", e))?;
+
+    let _ = app.emit("login-started", serde_json::json!({ "operation_id": operation_id }));
+    app.opener()
+        .open_url(auth_url, None::<&str>)
+        .map_err(|e| format!("Failed to open browser: {}", e))?;
+
+    let (code, returned_state) = tokio::select! {
+        callback = wait_for_login_callback(&listener, redirect.path()) => callback?,
+        _ = tokio::time::sleep(timeout) => return Err(LOGIN_TIMED_OUT_ERROR.to_string()),
+        _ = wait_for_cancel(cancel_flag) => return Err("Operation cancelled by user".to_string()),
+    };
+    drop(listener);
+
+    if returned_state.as_deref() != Some(expected_state.as_str()) {
+        return Err("Authentication error: login state mismatch, please start the login again".to_string());
+    }
+    exchange_code(code).await
+}
+
+async fn wait_for_cancel(cancel_flag: &AtomicBool) {
+    while !cancel_flag.load(Ordering::Relaxed) {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
+
+/// Accepts connections until the browser hits the redirect path, then answers with
+/// a small page and returns the authorization code and the `state` it came back with
+async fn wait_for_login_callback(
+    listener: &tokio::net::TcpListener,
+    redirect_path: &str,
+) -> Result<(String, Option<String>), String> {
+    loop {
+        let (mut stream, _) = listener
+            .accept()
+            .await
+            .map_err(|e| format!("Failed to accept login callback: {}", e))?;
+
+        let mut buffer = vec![0u8; 8192];
+        let read = stream.read(&mut buffer).await.unwrap_or(0);
+        let request = String::from_utf8_lossy(&buffer[..read]);
+        let target = request
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .unwrap_or("/");
+        let url = reqwest::Url::parse(&format!("http://localhost{}", target))
+            .map_err(|e| format!("Invalid login callback: {}", e))?;
+
+        if url.path() != redirect_path {
+            let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").await;
+            continue;
+        }
+
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let (page, result) = if let Some(error) = params.get("error") {
+            let description = params.get("error_description").unwrap_or(error);
+            ("Login failed, you can close this window.", Err(format!("Authentication error: {}", description)))
+        } else if let Some(code) = params.get("code") {
+            ("Login complete, you can close this window.", Ok((code.clone(), params.get("state").cloned())))
+        } else {
+            ("Login failed, you can close this window.", Err("Login callback did not contain a code".to_string()))
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            page.len(),
+            page
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+        return result;
+    }
+}
+
+#[tauri::command]
+async fn get_divisions() -> Result<Vec<Division>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = fetch_divisions(&cancel_flag, |_| {}).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_divisions", result).await
+}
+
+/// Streaming variant of `get_divisions` for organisations with thousands of
+/// divisions: every page is emitted as a `division-batch` event as soon as it
+/// arrives, and the command resolves with the total count.
+#[tauri::command]
+async fn stream_divisions(app: tauri::AppHandle) -> Result<usize, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let mut page = 0;
+    let result = fetch_divisions(&cancel_flag, |divisions| {
+        page += 1;
+        let _ = app.emit("division-batch", serde_json::json!({
+            "operation_id": operation_id,
+            "page": page,
+            "divisions": divisions
+        }));
+    })
+    .await
+    .map(|divisions| divisions.len());
+    finish_operation(&operation_id).await;
+    record_errors("stream_divisions", result).await
+}
+
+async fn fetch_divisions<F>(cancel_flag: &AtomicBool, mut on_page: F) -> Result<Vec<Division>, String>
+where
+    F: FnMut(&[Division]),
+{
+    let mut state_guard = get_app_state().await?;
+    let state = state_guard.as_mut().ok_or("State not initialized")?;
+
+    state.refresh_token().await?;
+
+    let division = state.discover_division().await?;
+    let attributes = "Code,Customer,CustomerCode,CustomerName,Description";
+    let path = format!(
+        "/v1/{}/system/Divisions?$select={}",
+        division, attributes
+    );
+
+    let mut all_results = Vec::new();
+    let mut next_path = Some(path);
+
+    while let Some(path) = next_path {
+        check_cancelled(cancel_flag)?;
+
+        let session = state.session()?;
+        let response = session.cancellable(session.get_conditional(&path), cancel_flag).await?;
+        let api_response: ApiResponse<Division> =
+            serde_json::from_value(response).map_err(|e| format!("Failed to parse divisions: {}", e))?;
+
+        on_page(&api_response.d.results);
+        all_results.extend(api_response.d.results);
+
+        next_path = api_response.d.__next.map(|next| {
+            next.strip_prefix(&state.api)
+                .unwrap_or(&next)
+                .to_string()
+        });
+    }
+
+    state.division_names = all_results
+        .iter()
+        .map(|d| (d.Code, d.Description.clone()))
+        .collect();
+
+    let locale = state.config.locale.clone().unwrap_or_default();
+    sort_divisions(&mut all_results, &locale);
+
+    Ok(all_results)
+}
+
+/// Orders divisions by customer name and description, see `collation_key`
+fn sort_divisions(divisions: &mut [Division], locale: &str) {
+    divisions.sort_by_cached_key(|d| {
+        let name = format!("{}{}", d.CustomerName, d.Description);
+        (collation_key(&name, locale), name)
+    });
+}
+
+/// Whether a division code is in the division listing, which is fetched first
+/// when no listing was made this session
+#[tauri::command]
+async fn division_exists(code: i32) -> Result<bool, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = known_division(code, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("division_exists", result).await
+}
+
+async fn known_division(division: i32, cancel_flag: &AtomicBool) -> Result<bool, String> {
+    {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        if !state.division_names.is_empty() {
+            return Ok(state.division_names.contains_key(&division));
+        }
+    }
+    fetch_divisions(cancel_flag, |_| {}).await?;
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    Ok(state.division_names.contains_key(&division))
+}
+
+/// Fails a fetch for a division that isn't listed before any of its pages are
+/// requested. When the divisions can't be listed, Exact gets to decide.
+async fn check_division(division: i32, cancel_flag: &AtomicBool) -> Result<(), String> {
+    match known_division(division, cancel_flag).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("{} {}", INVALID_DIVISION_ERROR, division)),
+        Err(e) if e.contains("cancelled") => Err(e),
+        Err(e) => {
+            eprintln!("[DIVISION] Could not check division {}: {}", division, e);
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DivisionAccess {
+    #[serde(flatten)]
+    division: Division,
+    accessible: bool,
+    /// Why the probe failed when it wasn't a 403; such divisions aren't cached
+    error: Option<String>,
+}
+
+/// Lists the divisions with whether the current token can actually query them,
+/// so the UI can gray out the ones that would 403. Each division is probed once
+/// with a `$top=1` request and remembered until logout; `refresh` probes again.
+#[tauri::command]
+async fn get_division_access(refresh: Option<bool>) -> Result<Vec<DivisionAccess>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = probe_divisions(&operation_id, &cancel_flag, refresh.unwrap_or(false)).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_division_access", result).await
+}
+
+async fn probe_divisions(
+    operation_id: &str,
+    cancel_flag: &AtomicBool,
+    refresh: bool,
+) -> Result<Vec<DivisionAccess>, String> {
+    let divisions = fetch_divisions(cancel_flag, |_| {}).await?;
+    let (session, max_parallel, mut known) = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        let known = if refresh { HashMap::new() } else { state.division_access.clone() };
+        (state.session()?, state.config.max_parallel_divisions.unwrap_or(3).max(1), known)
+    };
+
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(max_parallel));
+    let mut tasks = tokio::task::JoinSet::new();
+    for division in divisions.iter().map(|d| d.Code).filter(|code| !known.contains_key(code)) {
+        let session = session.clone();
+        let semaphore = semaphore.clone();
+        let operation_id = operation_id.to_string();
+        let path = format!("/v1/{}/financial/GLAccounts?$select=ID&$top=1", division);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (division, get_with_backoff(&session, &operation_id, &path).await)
+        });
+    }
+
+    // Returning early drops the JoinSet, which aborts the probes still queued
+    let mut errors = HashMap::new();
+    let mut probed = HashMap::new();
+    let mut ticker = tokio::time::interval(session.cancel_check_interval);
+    loop {
+        let joined = tokio::select! {
+            joined = tasks.join_next() => match joined {
+                Some(joined) => joined,
+                None => break,
+            },
+            _ = ticker.tick() => {
+                check_cancelled(cancel_flag)?;
+                continue;
+            }
+        };
+        match joined {
+            Ok((division, Ok(_))) => {
+                probed.insert(division, true);
+            }
+            Ok((division, Err(e))) if e.starts_with(DIVISION_ACCESS_LOST_ERROR) => {
+                probed.insert(division, false);
+            }
+            Ok((division, Err(e))) => {
+                eprintln!("[DIVISION {}] Probe of division {} failed: {}", operation_id, division, e);
+                errors.insert(division, e);
+            }
+            Err(e) => eprintln!("[DIVISION {}] Probe task panicked: {}", operation_id, e),
+        }
+    }
+
+    {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        if refresh {
+            state.division_access.clear();
+        }
+        state.division_access.extend(probed.iter().map(|(&code, &accessible)| (code, accessible)));
+    }
+    known.extend(probed);
+
+    Ok(divisions
+        .into_iter()
+        .map(|division| {
+            let error = errors.remove(&division.Code);
+            DivisionAccess {
+                accessible: known.get(&division.Code).copied().unwrap_or(false),
+                error,
+                division,
+            }
+        })
+        .collect())
+}
+
+/// Path of a division-less endpoint such as `current/Me`, which lives directly under
+/// `/v1/` instead of `/v1/{division}/`
+fn global_path(path: &str) -> Result<String, String> {
+    let path = path.trim().trim_start_matches('/');
+    let path = path.strip_prefix("v1/").unwrap_or(path);
+    let first_segment = path.split(['/', '?']).next().unwrap_or_default();
+    if first_segment.is_empty() || first_segment.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Not a division-less endpoint: {}", path));
+    }
+    if path.split(['/', '?']).any(|segment| segment == "..") {
+        return Err(format!("Invalid endpoint path: {}", path));
+    }
+    Ok(format!("/v1/{}", path))
+}
+
+/// Fetches every record of a division-less endpoint (e.g. `current/Me`), following
+/// `__next` links. Token refresh and rate-limit retries work as for division calls.
+#[tauri::command]
+async fn fetch_global(path: String) -> Result<Vec<serde_json::Value>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = fetch_global_records(&operation_id, &path, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("fetch_global", result).await
+}
+
+async fn fetch_global_records(
+    operation_id: &str,
+    path: &str,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<serde_json::Value>, String> {
+    let path = global_path(path)?;
+    let session = {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        state.refresh_token().await?;
+        state.session()?
+    };
+
+    let mut records = Vec::new();
+    let mut next_path = Some(path);
+    while let Some(path) = next_path {
+        check_cancelled(cancel_flag)?;
+        let response = get_with_backoff(&session, operation_id, &path).await?;
+        let page = extract_page(response)?;
+        records.extend(page.results);
+        next_path = page.__next.map(|next| {
+            next.strip_prefix(&session.api)
+                .unwrap_or(&next)
+                .to_string()
+        });
+    }
+    Ok(records)
+}
+
+/// Fetches all transaction lines of a division. Dates are returned as RFC3339
+/// unless `raw_dates` is set, in which case Exact's `/Date(ms)/` strings are kept.
+/// `expand_custom_fields` adds a `CustomField_<name>` column per custom field.
+/// A `label` names the fetch for `cancel_operation`, next to its operation id.
+#[tauri::command]
+async fn get_transactions(
+    app: tauri::AppHandle,
+    division: i32,
+    filter: Option<String>,
+    raw_dates: Option<bool>,
+    expand_custom_fields: Option<bool>,
+    label: Option<String>,
+) -> Result<Vec<Transaction>, String> {
+    let request = FetchRequest {
+        raw_dates: raw_dates.unwrap_or(false),
+        expand_custom_fields: expand_custom_fields.unwrap_or(false),
+        ..FetchRequest::transaction_lines(division, filter)
+    };
+    let result = fetch_collection_once(&app, &request, label).await;
+    remember_transactions(Some(division), &result).await;
+    record_errors("get_transactions", result).await
+}
+
+/// Runs `fetch_collection` as a new operation unless an identical request is
+/// already running. Depending on `duplicate_fetches` the repeat then fails with
+/// `DUPLICATE_FETCH_ERROR` naming the running operation, or waits for it and
+/// shares its result (a cancelled original cancels the joined fetch too).
+async fn fetch_collection_once(
+    app: &tauri::AppHandle,
+    request: &FetchRequest,
+    label: Option<String>,
+) -> Result<Vec<Transaction>, String> {
+    let join = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        match state.config.duplicate_fetches.as_deref().map(str::trim) {
+            None | Some("") | Some("reject") => false,
+            Some("join") => true,
+            Some(other) => return Err(format!("Unsupported duplicate_fetches in config: {}", other)),
+        }
+    };
+    let key = serde_json::to_string(request).map_err(|e| format!("Failed to serialize request: {}", e))?;
+
+    let mut in_flight = IN_FLIGHT_FETCHES.lock().await;
+    if let Some(running) = in_flight.get(&key) {
+        if !join {
+            return Err(format!("{} {}", DUPLICATE_FETCH_ERROR, running.operation_id));
+        }
+        eprintln!("[FETCH {}] Joining identical fetch", running.operation_id);
+        let mut result = running.result.clone();
+        drop(in_flight);
+        return match result.wait_for(Option::is_some).await {
+            Ok(done) => (*done).clone().unwrap_or_else(|| Err("Joined fetch ended without a result".to_string())),
+            Err(_) => Err("Joined fetch ended without a result".to_string()),
+        };
+    }
+    let (operation_id, cancel_flag) = begin_labeled_operation(label).await?;
+    let (result_tx, result_rx) = tokio::sync::watch::channel(None);
+    in_flight.insert(
+        key.clone(),
+        InFlightFetch {
+            operation_id: operation_id.clone(),
+            result: result_rx,
+        },
+    );
+    drop(in_flight);
+
+    let result = fetch_collection(app, &operation_id, request, &cancel_flag).await;
+    IN_FLIGHT_FETCHES.lock().await.remove(&key);
+    finish_operation(&operation_id).await;
+    // No receivers left only means nothing joined
+    let _ = result_tx.send(Some(result.clone()));
+    result
+}
+
+/// Parameters of a generic collection fetch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FetchRequest {
+    division: i32,
+    endpoint: String,
+    select: Option<Vec<String>>,
+    filter: Option<String>,
+    expand: Option<Vec<String>>,
+    orderby: Option<String>,
+    top: Option<u32>,
+    /// Keep Exact's native `/Date(ms)/` strings instead of converting them to RFC3339
+    #[serde(default)]
+    raw_dates: bool,
+    /// Expand transaction lines' `CustomField` into named `CustomField_<name>` columns
+    #[serde(default)]
+    expand_custom_fields: bool,
+    /// Replace smart quotes in the filter, as left behind by pasting from documents
+    #[serde(default)]
+    normalize_quotes: bool,
+    /// Lookups made on the side of another fetch (e.g. VAT codes) don't emit progress
+    /// or completion events, which would be mistaken for the main fetch's
+    #[serde(skip)]
+    silent: bool,
+}
+
+impl FetchRequest {
+    fn transaction_lines(division: i32, filter: Option<String>) -> Self {
+        FetchRequest {
+            division,
+            endpoint: TRANSACTION_LINES_ENDPOINT.to_string(),
+            filter,
+            ..Default::default()
+        }
+    }
+}
+
+/// Navigation properties that can be `$expand`ed per endpoint. The bulk endpoints
+/// don't support `$expand` at all; endpoints not listed here are passed through
+/// unchecked and Exact will reject unknown properties itself.
+const EXPANDABLE_ENDPOINTS: &[(&str, &[&str])] = &[
+    ("salesorder/SalesOrders", &["SalesOrderLines"]),
+    ("salesinvoice/SalesInvoices", &["SalesInvoiceLines"]),
+    ("purchaseorder/PurchaseOrders", &["PurchaseOrderLines"]),
+    ("purchaseentry/PurchaseEntries", &["PurchaseEntryLines"]),
+    ("salesentry/SalesEntries", &["SalesEntryLines"]),
+    ("generaljournalentry/GeneralJournalEntries", &["GeneralJournalEntryLines"]),
+    ("financialtransaction/BankEntries", &["BankEntryLines"]),
+    ("project/Projects", &["BudgetedHoursPerHourType", "BudgetedCosts"]),
+];
+
+/// Transaction line fields that aren't part of the default `TRANSACTION_FIELDS` selection
+const TRANSACTION_LINE_EXTRA_FIELDS: &[&str] = &[
+    "Account", "Asset", "Created", "Creator", "Date", "EntryID", "GLAccount", "ID", "LineNumber",
+    "Modifier", "OffsetID",
+];
+
+/// Fields that can be `$select`ed per endpoint, so fields of one entity aren't sent
+/// to another. Their types follow `field_kind`. The transaction line endpoints are
+/// resolved in `selectable_fields`; endpoints not listed are passed through unchecked.
+const SELECTABLE_FIELDS: &[(&str, &[&str])] = &[
+    (
+        "vat/VATCodes",
+        &[
+            "Account", "AccountCode", "AccountName", "CalculationBasis", "Charged", "Code", "Country",
+            "Created", "Creator", "CreatorFullName", "Description", "Division", "EUSalesListing",
+            "GLDiscountPurchase", "GLDiscountSales", "GLToClaim", "GLToPay", "ID", "IntraStat",
+            "IntraStatType", "IsBlocked", "LegalText", "Modified", "Modifier", "ModifierFullName",
+            "Percentage", "TaxReturnType", "Type", "VatDocType", "VatMargin", "VATPartialRatio",
+            "VATPercentages", "VATTransactionType",
+        ],
+    ),
+    (
+        "financial/Journals",
+        &[
+            "AllowVariableCurrency", "AllowVariableExchangeRate", "AllowVAT", "AutoSave", "Bank",
+            "BankAccountBICCode", "BankAccountCountry", "BankAccountDescription", "BankAccountIBAN",
+            "BankAccountID", "BankAccountIncludingMask", "BankAccountUseSEPA",
+            "BankAccountUseSepaDirectDebit", "BankName", "Code", "Created", "Creator", "CreatorFullName",
+            "Currency", "CurrencyDescription", "Description", "Division", "GLAccount", "GLAccountCode",
+            "GLAccountDescription", "GLAccountType", "ID", "IsBlocked", "Modified", "Modifier",
+            "ModifierFullName", "PaymentInTransitAccount", "PaymentServiceAccountIdentifier",
+            "PaymentServiceProvider", "PaymentServiceProviderName", "Type",
+        ],
+    ),
+    (
+        "hrm/Costcenters",
+        &[
+            "Active", "Code", "Created", "Creator", "CreatorFullName", "Description", "Division", "ID",
+            "Modified", "Modifier", "ModifierFullName",
+        ],
+    ),
+    (
+        "hrm/Costunits",
+        &[
+            "Account", "AccountCode", "AccountName", "Code", "Created", "Creator", "CreatorFullName",
+            "Description", "Division", "ID", "Modified", "Modifier", "ModifierFullName",
+        ],
+    ),
+];
+
+fn selectable_fields(endpoint: &str) -> Option<Vec<&'static str>> {
+    match endpoint {
+        TRANSACTION_LINES_ENDPOINT
+        | "financialtransaction/TransactionLines"
+        | "sync/Financial/TransactionLines" => {
+            let mut fields: Vec<&str> = TRANSACTION_FIELDS
+                .split(',')
+                .chain(TRANSACTION_LINE_EXTRA_FIELDS.iter().copied())
+                .collect();
+            if endpoint.starts_with("sync/") {
+                fields.push("Timestamp");
+            }
+            Some(fields)
+        }
+        _ => SELECTABLE_FIELDS
+            .iter()
+            .find(|(e, _)| *e == endpoint)
+            .map(|(_, fields)| fields.to_vec()),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedQuery {
+    name: String,
+    division: i32,
+    endpoint: String,
+    filter: Option<String>,
+    select: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+struct DivisionFailure {
+    division: i32,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+struct MultiDivisionResult {
+    transactions: Vec<Transaction>,
+    succeeded: Vec<i32>,
+    failed: Vec<DivisionFailure>,
+}
+
+/// Fetches the same report across several divisions, a few at a time. A failing
+/// division doesn't abort the others; it is reported in `failed` instead.
+/// `label` works as for `get_transactions`.
+#[tauri::command]
+async fn get_transactions_multi(
+    app: tauri::AppHandle,
+    divisions: Vec<i32>,
+    filter: Option<String>,
+    raw_dates: Option<bool>,
+    label: Option<String>,
+) -> Result<MultiDivisionResult, String> {
+    let max_parallel = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        state.config.max_parallel_divisions.unwrap_or(3).max(1)
+    };
+
+    let (operation_id, cancel_flag) = match begin_labeled_operation(label).await {
+        Ok(operation) => operation,
+        Err(e) => return record_errors("get_transactions_multi", Err(e)).await,
+    };
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(max_parallel));
+    let mut tasks = tokio::task::JoinSet::new();
+
+    for division in divisions {
+        let app = app.clone();
+        let operation_id = operation_id.clone();
+        let cancel_flag = cancel_flag.clone();
+        let request = FetchRequest {
+            raw_dates: raw_dates.unwrap_or(false),
+            ..FetchRequest::transaction_lines(division, filter.clone())
+        };
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let _ = app.emit("division-progress", serde_json::json!({
+                "operation_id": operation_id,
+                "division": division,
+                "status": "started"
+            }));
+            let result = fetch_collection(&app, &operation_id, &request, &cancel_flag).await;
+            let _ = app.emit("division-progress", serde_json::json!({
+                "operation_id": operation_id,
+                "division": division,
+                "status": if result.is_ok() { "done" } else { "failed" },
+                "count": result.as_ref().map(|t| t.len()).unwrap_or(0)
+            }));
+            (division, result)
+        });
+    }
+
+    let mut combined = MultiDivisionResult {
+        transactions: Vec::new(),
+        succeeded: Vec::new(),
+        failed: Vec::new(),
+    };
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((division, Ok(transactions))) => {
+                combined.succeeded.push(division);
+                combined.transactions.extend(transactions);
+            }
+            Ok((division, Err(error))) => {
+                combined.failed.push(DivisionFailure { division, error });
+            }
+            Err(e) => eprintln!("[MULTI] Division task panicked: {}", e),
+        }
+    }
+    finish_operation(&operation_id).await;
+
+    combined.succeeded.sort();
+    combined.failed.sort_by_key(|f| f.division);
+
+    let result = if cancel_flag.load(Ordering::Relaxed) {
+        Err("Operation cancelled by user".to_string())
+    } else {
+        Ok(combined.transactions.clone())
+    };
+    remember_transactions(None, &result).await;
+    record_errors("get_transactions_multi", result).await?;
+
+    Ok(combined)
+}
+
+/// Splits `[from, to]` (inclusive dates) into consecutive half-open ranges of
+/// `months` months, so every day falls in exactly one chunk
+fn date_chunks(
+    from: chrono::NaiveDate,
+    to: chrono::NaiveDate,
+    months: u32,
+) -> Result<Vec<(chrono::NaiveDate, chrono::NaiveDate)>, String> {
+    if from > to {
+        return Err(format!("Date range starts after it ends: {} to {}", from, to));
+    }
+    let end = to.succ_opt().ok_or("Date range ends too late")?;
+    let mut chunks = Vec::new();
+    let mut start = from;
+    while start < end {
+        let next = start
+            .checked_add_months(chrono::Months::new(months))
+            .ok_or("Date range ends too late")?
+            .min(end);
+        chunks.push((start, next));
+        start = next;
+    }
+    Ok(chunks)
+}
+
+/// Fetches transaction lines for a long date range one chunk of `chunk_months`
+/// (default 1) at a time, so each request stays bounded. `field` is the date
+/// field the range applies to, e.g. `DueDate` or `Modified`; `from` and `to` are
+/// inclusive `YYYY-MM-DD` dates. `ID` is selected along with the usual fields, and
+/// a line that turns up in two chunks (modified while the fetch runs) is kept
+/// once; identical but distinct lines are all kept. Emits `chunk-progress` after
+/// every chunk.
+#[tauri::command]
+async fn get_transactions_chunked(
+    app: tauri::AppHandle,
+    division: i32,
+    field: String,
+    from: String,
+    to: String,
+    chunk_months: Option<u32>,
+    filter: Option<String>,
+) -> Result<Vec<Transaction>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = async {
+        if !DATE_FIELDS.contains(&field.as_str()) {
+            return Err(format!("{} is not a date field", field));
+        }
+        let parse = |date: &str| {
+            chrono::NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+                .map_err(|e| format!("Invalid date {}: {}", date, e))
+        };
+        let chunks = date_chunks(parse(&from)?, parse(&to)?, chunk_months.unwrap_or(1).max(1))?;
+        let filter = filter.map(|f| f.trim().to_string()).filter(|f| !f.is_empty());
+        let select: Vec<String> = TRANSACTION_FIELDS
+            .split(',')
+            .chain(std::iter::once("ID"))
+            .map(String::from)
+            .collect();
+
+        let mut seen = BTreeSet::new();
+        let mut transactions = Vec::new();
+        let mut reduced_pages = 0;
+        for (index, (start, end)) in chunks.iter().enumerate() {
+            check_cancelled(&cancel_flag)?;
+            let range = format!(
+                "{field} ge datetime'{}' and {field} lt datetime'{}'",
+                start.format("%Y-%m-%d"),
+                end.format("%Y-%m-%d"),
+            );
+            let request = FetchRequest {
+                select: Some(select.clone()),
+                silent: true,
+                ..FetchRequest::transaction_lines(
+                    division,
+                    Some(match &filter {
+                        Some(f) => format!("({}) and {}", f, range),
+                        None => range,
+                    }),
+                )
+            };
+            let (rows, reduced) = fetch_collection_counted(&app, &operation_id, &request, &cancel_flag).await?;
+            reduced_pages += reduced;
+            let fetched = rows.len();
+            for row in rows {
+                // A line without an ID can't be matched up, so it is always kept
+                let id = row.data.get("ID").and_then(|id| id.as_str()).map(str::to_lowercase);
+                if id.is_none_or(|id| seen.insert(id)) {
+                    transactions.push(row);
+                }
+            }
+            eprintln!(
+                "[FETCH {}] Chunk {}/{} ({} to {}): {} rows",
+                operation_id,
+                index + 1,
+                chunks.len(),
+                start,
+                end,
+                fetched
+            );
+            let _ = app.emit("chunk-progress", serde_json::json!({
+                "operation_id": operation_id,
+                "division": division,
+                "chunk": index + 1,
+                "chunks": chunks.len(),
+                "from": start.to_string(),
+                "to": end.to_string(),
+                "rows": transactions.len()
+            }));
+        }
+        Ok::<_, String>((transactions, reduced_pages))
+    }
+    .await;
+    // Completion reads the operation's retries, so it goes out before the operation ends
+    if let Ok((rows, reduced_pages)) = &result {
+        emit_transactions_complete(&app, &operation_id, rows.len(), *reduced_pages).await;
+    }
+    finish_operation(&operation_id).await;
+    let result = result.map(|(rows, _)| rows);
+    remember_transactions(Some(division), &result).await;
+    record_errors("get_transactions_chunked", result).await
+}
+
+/// Runs a collection fetch as its own operation and returns the rows as plain
+/// JSON objects, for the entity commands that don't need the `Transaction` wrapper
+async fn fetch_records(
+    command: &str,
+    app: &tauri::AppHandle,
+    request: FetchRequest,
+) -> Result<Vec<serde_json::Value>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = fetch_collection(app, &operation_id, &request, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    let records = result.map(|rows| {
+        rows.into_iter()
+            .map(|row| serde_json::Value::Object(row.data.into_iter().collect()))
+            .collect()
+    });
+    record_errors(command, records).await
+}
+
+const SALES_INVOICE_FIELDS: &[&str] = &[
+    "InvoiceID",
+    "InvoiceNumber",
+    "InvoiceDate",
+    "AmountDC",
+    "Status",
+    "OrderNumber",
+    "YourRef",
+];
+
+#[tauri::command]
+async fn get_sales_invoices(
+    app: tauri::AppHandle,
+    division: i32,
+    filter: Option<String>,
+) -> Result<Vec<serde_json::Value>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: "salesinvoice/SalesInvoices".to_string(),
+        select: Some(SALES_INVOICE_FIELDS.iter().map(|f| f.to_string()).collect()),
+        filter,
+        ..Default::default()
+    };
+    fetch_records("get_sales_invoices", &app, request).await
+}
+
+const BANK_ENTRY_LINE_FIELDS: &[&str] = &[
+    "ID",
+    "EntryID",
+    "EntryNumber",
+    "LineNumber",
+    "Date",
+    "AccountCode",
+    "AccountName",
+    "AmountDC",
+    "AmountFC",
+    "Description",
+    "GLAccountCode",
+    "GLAccountDescription",
+    "OurRef",
+    "PaymentReference",
+    "Notes",
+];
+
+/// Bank entry lines for reconciliation, from `financialtransaction/BankEntryLines`
+#[tauri::command]
+async fn get_bank_entries(
+    app: tauri::AppHandle,
+    division: i32,
+    filter: Option<String>,
+) -> Result<Vec<serde_json::Value>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: "financialtransaction/BankEntryLines".to_string(),
+        select: Some(BANK_ENTRY_LINE_FIELDS.iter().map(|f| f.to_string()).collect()),
+        filter,
+        ..Default::default()
+    };
+    fetch_records("get_bank_entries", &app, request).await
+}
+
+const SUBSCRIPTION_FIELDS: &[&str] = &[
+    "EntryID",
+    "Number",
+    "Description",
+    "OrderedByName",
+    "InvoiceToName",
+    "SubscriptionTypeCode",
+    "SubscriptionTypeDescription",
+    "StartDate",
+    "EndDate",
+    "CancellationDate",
+    "InvoicingStartDate",
+    "InvoicedTo",
+    "Currency",
+    "YourRef",
+];
+
+/// Subscriptions for recurring revenue reporting, from `subscription/Subscriptions`
+#[tauri::command]
+async fn get_subscriptions(
+    app: tauri::AppHandle,
+    division: i32,
+    filter: Option<String>,
+) -> Result<Vec<serde_json::Value>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: "subscription/Subscriptions".to_string(),
+        select: Some(SUBSCRIPTION_FIELDS.iter().map(|f| f.to_string()).collect()),
+        filter,
+        ..Default::default()
+    };
+    fetch_records("get_subscriptions", &app, request).await
+}
+
+/// A division's lookup list from `request` (fetched silently), each row parsed into `T`
+async fn lookup_list<T: serde::de::DeserializeOwned>(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    request: &FetchRequest,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<T>, String> {
+    let request = FetchRequest { silent: true, ..request.clone() };
+    fetch_collection_pages(app, operation_id, &request, cancel_flag)
+        .await?
+        .into_iter()
+        .map(|row| serde_json::from_value(serde_json::Value::Object(row.data.into_iter().collect())))
+        .collect::<Result<Vec<T>, _>>()
+        .map_err(|e| format!("Failed to parse {}: {}", request.endpoint, e))
+}
+
+/// `lookup_list` sorted by `sort_key` and kept under `key` in the `AppState` map
+/// `cache` picks, so later calls skip the fetch until the division changes
+async fn cached_lookup<K, T>(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    request: &FetchRequest,
+    cancel_flag: &AtomicBool,
+    cache: fn(&mut AppState) -> &mut HashMap<K, Vec<T>>,
+    key: K,
+    sort_key: Option<fn(&T) -> String>,
+) -> Result<Vec<T>, String>
+where
+    K: Eq + std::hash::Hash,
+    T: serde::de::DeserializeOwned + Clone,
+{
+    {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        if let Some(items) = cache(state).get(&key) {
+            return Ok(items.clone());
+        }
+    }
+
+    let mut items = lookup_list(app, operation_id, request, cancel_flag).await?;
+    if let Some(sort_key) = sort_key {
+        items.sort_by_cached_key(sort_key);
+    }
+
+    let mut state_guard = get_app_state().await?;
+    let state = state_guard.as_mut().ok_or("State not initialized")?;
+    cache(state).insert(key, items.clone());
+    Ok(items)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct VatCode {
+    Code: String,
+    Description: Option<String>,
+    Type: Option<String>,
+}
+
+/// VAT codes of a division from `vat/VATCodes`, cached until the division changes
+#[tauri::command]
+async fn get_vat_codes(app: tauri::AppHandle, division: i32) -> Result<Vec<VatCode>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = vat_codes(&app, &operation_id, division, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_vat_codes", result).await
+}
+
+async fn vat_codes(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<VatCode>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: "vat/VATCodes".to_string(),
+        select: Some(vec!["Code".to_string(), "Description".to_string(), "Type".to_string()]),
+        ..Default::default()
+    };
+    cached_lookup(app, operation_id, &request, cancel_flag, |state| &mut state.vat_codes, division, None).await
+}
+
+fn is_blank(value: Option<&serde_json::Value>) -> bool {
+    match value {
+        None | Some(serde_json::Value::Null) => true,
+        Some(serde_json::Value::String(s)) => s.trim().is_empty(),
+        _ => false,
+    }
+}
+
+/// Fills in descriptions Exact left blank on transaction lines. Lookup failures are
+/// logged and leave the rows as they were; only cancellation is passed on.
+async fn enrich_transactions(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    rows: &mut [Transaction],
+    cancel_flag: &AtomicBool,
+) -> Result<(), String> {
+    if let Err(e) = fill_vat_descriptions(app, operation_id, division, rows, cancel_flag).await {
+        if e.contains("cancelled") {
+            return Err(e);
+        }
+        eprintln!("[ENRICH {}] VAT description lookup failed: {}", operation_id, e);
+    }
+    if let Err(e) = fill_cost_descriptions(app, operation_id, division, rows, cancel_flag).await {
+        if e.contains("cancelled") {
+            return Err(e);
+        }
+        eprintln!("[ENRICH {}] Cost center/unit lookup failed: {}", operation_id, e);
+    }
+    if let Err(e) = fill_journal_descriptions(app, operation_id, division, rows, cancel_flag).await {
+        if e.contains("cancelled") {
+            return Err(e);
+        }
+        eprintln!("[ENRICH {}] Journal lookup failed: {}", operation_id, e);
+    }
+    if let Err(e) = fill_project_descriptions(app, operation_id, division, rows, cancel_flag).await {
+        if e.contains("cancelled") {
+            return Err(e);
+        }
+        eprintln!("[ENRICH {}] Project lookup failed: {}", operation_id, e);
+    }
+    if let Err(e) = fill_missing_descriptions(app, operation_id, division, rows, cancel_flag).await {
+        if e.contains("cancelled") {
+            return Err(e);
+        }
+        eprintln!("[ENRICH {}] Description lookup failed: {}", operation_id, e);
+    }
+    Ok(())
+}
+
+/// A description column that is looked up per distinct code when it comes back blank
+struct DescriptionLookup {
+    code_field: &'static str,
+    description_field: &'static str,
+    endpoint: &'static str,
+    source_field: &'static str,
+}
+
+const DESCRIPTION_LOOKUPS: &[DescriptionLookup] = &[
+    DescriptionLookup {
+        code_field: "GLAccountCode",
+        description_field: "GLAccountDescription",
+        endpoint: "financial/GLAccounts",
+        source_field: "Description",
+    },
+    DescriptionLookup {
+        code_field: "AccountCode",
+        description_field: "AccountName",
+        endpoint: "crm/Accounts",
+        source_field: "Name",
+    },
+];
+
+/// One request per distinct code, at most `enrichment_concurrency` at a time so a
+/// large set of codes doesn't run into Exact's rate limit. Progress is reported
+/// through `enrichment-progress` events.
+async fn fill_missing_descriptions(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    rows: &mut [Transaction],
+    cancel_flag: &AtomicBool,
+) -> Result<(), String> {
+    let needs_lookup = |row: &Transaction, lookup: &DescriptionLookup| {
+        !is_blank(row.data.get(lookup.code_field)) && is_blank(row.data.get(lookup.description_field))
+    };
+    if !rows
+        .iter()
+        .any(|row| DESCRIPTION_LOOKUPS.iter().any(|lookup| needs_lookup(row, lookup)))
+    {
+        return Ok(());
+    }
+
+    let (session, max_parallel) = {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        state.refresh_token().await?;
+        (state.session()?, state.config.enrichment_concurrency.unwrap_or(4).max(1))
+    };
+
+    for lookup in DESCRIPTION_LOOKUPS {
+        let codes: BTreeSet<String> = rows
+            .iter()
+            .filter(|row| needs_lookup(row, lookup))
+            .filter_map(|row| row.data.get(lookup.code_field).and_then(|v| v.as_str()))
+            .map(String::from)
+            .collect();
+        if codes.is_empty() {
+            continue;
+        }
+
+        let total = codes.len();
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(max_parallel));
+        let mut tasks = tokio::task::JoinSet::new();
+        for code in codes {
+            let session = session.clone();
+            let semaphore = semaphore.clone();
+            let filter = format!("Code eq '{}'", code.replace('\'', "''"));
+            let path = format!(
+                "/v1/{}/{}?$select={}&$filter={}",
+                division,
+                lookup.endpoint,
+                lookup.source_field,
+                urlencoding::encode(&filter)
+            );
+            let operation_id = operation_id.to_string();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let result = get_with_backoff(&session, &operation_id, &path).await;
+                (code, result)
+            });
+        }
+
+        // Returning early drops the JoinSet, which aborts the lookups still queued
+        let mut descriptions = HashMap::new();
+        let mut done = 0;
+        let mut ticker = tokio::time::interval(session.cancel_check_interval);
+        loop {
+            let joined = tokio::select! {
+                joined = tasks.join_next() => match joined {
+                    Some(joined) => joined,
+                    None => break,
+                },
+                _ = ticker.tick() => {
+                    check_cancelled(cancel_flag)?;
+                    continue;
+                }
+            };
+            done += 1;
+            match joined {
+                Ok((code, Ok(response))) => {
+                    let description = extract_page(response)
+                        .ok()
+                        .and_then(|page| page.results.into_iter().next())
+                        .and_then(|record| {
+                            record.get(lookup.source_field).and_then(|v| v.as_str()).map(String::from)
+                        });
+                    if let Some(description) = description {
+                        descriptions.insert(code, description);
+                    }
+                }
+                Ok((code, Err(e))) => {
+                    eprintln!("[ENRICH {}] {} lookup for {} failed: {}", operation_id, lookup.endpoint, code, e)
+                }
+                Err(e) => eprintln!("[ENRICH {}] Lookup task panicked: {}", operation_id, e),
+            }
+            let _ = app.emit("enrichment-progress", serde_json::json!({
+                "operation_id": operation_id,
+                "division": division,
+                "field": lookup.description_field,
+                "done": done,
+                "total": total
+            }));
+        }
+
+        for row in rows.iter_mut() {
+            if !needs_lookup(row, lookup) {
+                continue;
+            }
+            let description = row
+                .data
+                .get(lookup.code_field)
+                .and_then(|code| code.as_str())
+                .and_then(|code| descriptions.get(code));
+            if let Some(description) = description {
+                row.data.insert(
+                    lookup.description_field.to_string(),
+                    serde_json::Value::String(description.clone()),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Retries a rate-limited request after the wait Exact asked for, a few times.
+/// These are repeated lookups of reference data, so they go out conditionally.
+async fn get_with_backoff(
+    session: &ApiSession,
+    operation_id: &str,
+    path: &str,
+) -> Result<serde_json::Value, String> {
+    let mut attempts = 0;
+    loop {
+        match session.get_conditional(path).await {
+            Err(e) if e.starts_with(RATE_LIMITED_ERROR) && attempts < 3 => {
+                attempts += 1;
+                spend_retry(operation_id, session.retry_budget).await?;
+                let wait = e[RATE_LIMITED_ERROR.len()..].trim().parse::<u64>().unwrap_or(5);
+                tokio::time::sleep(std::time::Duration::from_secs(wait.clamp(1, 60))).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Exact sometimes leaves `VATCodeDescription` blank on transaction lines; fill it
+/// in from the division's VAT codes. The lookup is skipped when nothing is missing.
+async fn fill_vat_descriptions(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    rows: &mut [Transaction],
+    cancel_flag: &AtomicBool,
+) -> Result<(), String> {
+    let missing = rows.iter().any(|row| {
+        !is_blank(row.data.get("VATCode")) && is_blank(row.data.get("VATCodeDescription"))
+    });
+    if !missing {
+        return Ok(());
+    }
+
+    let descriptions: HashMap<String, String> = vat_codes(app, operation_id, division, cancel_flag)
+        .await?
+        .into_iter()
+        .filter_map(|code| Some((code.Code.trim().to_string(), code.Description?)))
+        .collect();
+    fill_descriptions(rows, "VATCode", "VATCodeDescription", &descriptions);
+    Ok(())
+}
+
+/// Sets `description_field` from `descriptions` (keyed by trimmed code) where it is blank
+fn fill_descriptions(
+    rows: &mut [Transaction],
+    code_field: &str,
+    description_field: &str,
+    descriptions: &HashMap<String, String>,
+) {
+    for row in rows.iter_mut() {
+        if !is_blank(row.data.get(description_field)) {
+            continue;
+        }
+        let description = row
+            .data
+            .get(code_field)
+            .and_then(|code| code.as_str())
+            .and_then(|code| descriptions.get(code.trim()));
+        if let Some(description) = description {
+            row.data.insert(
+                description_field.to_string(),
+                serde_json::Value::String(description.clone()),
+            );
+        }
+    }
+}
+
+/// A cost center or cost unit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct CodeDescription {
+    Code: String,
+    Description: Option<String>,
+}
+
+const COST_CENTERS_ENDPOINT: &str = "hrm/Costcenters";
+const COST_UNITS_ENDPOINT: &str = "hrm/Costunits";
+
+/// Cost centers of a division, sorted by code and cached until the division changes
+#[tauri::command]
+async fn get_cost_centers(app: tauri::AppHandle, division: i32) -> Result<Vec<CodeDescription>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = code_list(&app, &operation_id, division, COST_CENTERS_ENDPOINT, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_cost_centers", result).await
+}
+
+/// Cost units of a division, sorted by code and cached until the division changes
+#[tauri::command]
+async fn get_cost_units(app: tauri::AppHandle, division: i32) -> Result<Vec<CodeDescription>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = code_list(&app, &operation_id, division, COST_UNITS_ENDPOINT, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_cost_units", result).await
+}
+
+async fn code_list(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    endpoint: &str,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<CodeDescription>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: endpoint.to_string(),
+        select: Some(vec!["Code".to_string(), "Description".to_string()]),
+        ..Default::default()
+    };
+    cached_lookup(
+        app,
+        operation_id,
+        &request,
+        cancel_flag,
+        |state| &mut state.code_lists,
+        (division, endpoint.to_string()),
+        Some(|code: &CodeDescription| code.Code.trim().to_string()),
+    )
+    .await
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct Journal {
+    Code: String,
+    Description: Option<String>,
+    Type: Option<i32>,
+    GLAccountCode: Option<String>,
+}
+
+const JOURNALS_ENDPOINT: &str = "financial/Journals";
+
+/// Journals of a division, sorted by code and cached until the division changes
+#[tauri::command]
+async fn get_journals(app: tauri::AppHandle, division: i32) -> Result<Vec<Journal>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = journals(&app, &operation_id, division, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_journals", result).await
+}
+
+async fn journals(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<Journal>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: JOURNALS_ENDPOINT.to_string(),
+        select: Some(
+            ["Code", "Description", "Type", "GLAccountCode"]
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+        ),
+        ..Default::default()
+    };
+    cached_lookup(
+        app,
+        operation_id,
+        &request,
+        cancel_flag,
+        |state| &mut state.journals,
+        division,
+        Some(|journal: &Journal| journal.Code.trim().to_string()),
+    )
+    .await
+}
+
+async fn fill_journal_descriptions(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    rows: &mut [Transaction],
+    cancel_flag: &AtomicBool,
+) -> Result<(), String> {
+    let missing = rows.iter().any(|row| {
+        !is_blank(row.data.get("JournalCode")) && is_blank(row.data.get("JournalDescription"))
+    });
+    if !missing {
+        return Ok(());
+    }
+
+    let descriptions: HashMap<String, String> = journals(app, operation_id, division, cancel_flag)
+        .await?
+        .into_iter()
+        .filter_map(|journal| Some((journal.Code.trim().to_string(), journal.Description?)))
+        .collect();
+    fill_descriptions(rows, "JournalCode", "JournalDescription", &descriptions);
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct Project {
+    Code: String,
+    Description: Option<String>,
+    Account: Option<String>,
+    Status: Option<i32>,
+    StartDate: Option<String>,
+    EndDate: Option<String>,
+}
+
+const PROJECTS_ENDPOINT: &str = "project/Projects";
+
+/// Projects of a division, sorted by code. Dates are RFC3339; the unfiltered list
+/// is cached until the division changes.
+#[tauri::command]
+async fn get_projects(
+    app: tauri::AppHandle,
+    division: i32,
+    filter: Option<String>,
+) -> Result<Vec<Project>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = projects(&app, &operation_id, division, filter, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_projects", result).await
+}
+
+async fn projects(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    filter: Option<String>,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<Project>, String> {
+    let filter = filter.filter(|f| !f.trim().is_empty());
+    let request = FetchRequest {
+        division,
+        endpoint: PROJECTS_ENDPOINT.to_string(),
+        select: Some(
+            ["Code", "Description", "Account", "Status", "StartDate", "EndDate"]
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+        ),
+        filter: filter.clone(),
+        ..Default::default()
+    };
+    let project_code = |project: &Project| project.Code.trim().to_string();
+    // Only the full list is cached; a filtered one is fetched every time
+    if filter.is_some() {
+        let mut projects = lookup_list(app, operation_id, &request, cancel_flag).await?;
+        projects.sort_by_cached_key(project_code);
+        return Ok(projects);
+    }
+    cached_lookup(app, operation_id, &request, cancel_flag, |state| &mut state.projects, division, Some(project_code))
+        .await
+}
+
+async fn fill_project_descriptions(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    rows: &mut [Transaction],
+    cancel_flag: &AtomicBool,
+) -> Result<(), String> {
+    let missing = rows.iter().any(|row| {
+        !is_blank(row.data.get("ProjectCode")) && is_blank(row.data.get("ProjectDescription"))
+    });
+    if !missing {
+        return Ok(());
+    }
+
+    let descriptions: HashMap<String, String> = projects(app, operation_id, division, None, cancel_flag)
+        .await?
+        .into_iter()
+        .filter_map(|project| Some((project.Code.trim().to_string(), project.Description?)))
+        .collect();
+    fill_descriptions(rows, "ProjectCode", "ProjectDescription", &descriptions);
+    Ok(())
+}
+
+const GL_CLASSIFICATION_FIELDS: &[&str] = &[
+    "ID",
+    "Code",
+    "Name",
+    "Description",
+    "Parent",
+    "Abstract",
+    "Balance",
+    "PeriodType",
+    "Type",
+    "TypeDescription",
+    "TaxonomyNamespaceDescription",
+    "Modified",
+];
+
+/// GL classifications of a division from `financial/GLClassifications`, the report
+/// lines GL accounts are mapped onto; `Parent` links them into a tree. Cached until
+/// the division changes.
+#[tauri::command]
+async fn get_gl_classifications(
+    app: tauri::AppHandle,
+    division: i32,
+) -> Result<Vec<serde_json::Value>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = gl_classifications(&app, &operation_id, division, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_gl_classifications", result).await
+}
+
+async fn gl_classifications(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<serde_json::Value>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: "financial/GLClassifications".to_string(),
+        select: Some(GL_CLASSIFICATION_FIELDS.iter().map(|f| f.to_string()).collect()),
+        ..Default::default()
+    };
+    cached_lookup(
+        app,
+        operation_id,
+        &request,
+        cancel_flag,
+        |state| &mut state.gl_classifications,
+        division,
+        None,
+    )
+    .await
+}
+
+/// A payment condition; `PaymentDays` is the term its invoices fall due after
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct PaymentCondition {
+    Code: String,
+    Description: Option<String>,
+    PaymentDays: Option<i32>,
+    Modified: Option<String>,
+}
+
+const PAYMENT_CONDITION_FIELDS: &[&str] = &["Code", "Description", "PaymentDays", "Modified"];
+
+/// Payment conditions of a division from `cashflow/PaymentConditions`, for aging
+/// reports: `PaymentDays` is the term a condition's invoices fall due after.
+/// Sorted by code and cached until the division changes.
+#[tauri::command]
+async fn get_payment_conditions(
+    app: tauri::AppHandle,
+    division: i32,
+) -> Result<Vec<PaymentCondition>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = payment_conditions(&app, &operation_id, division, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_payment_conditions", result).await
+}
+
+async fn payment_conditions(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<PaymentCondition>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: "cashflow/PaymentConditions".to_string(),
+        select: Some(PAYMENT_CONDITION_FIELDS.iter().map(|f| f.to_string()).collect()),
+        ..Default::default()
+    };
+    cached_lookup(
+        app,
+        operation_id,
+        &request,
+        cancel_flag,
+        |state| &mut state.payment_conditions,
+        division,
+        Some(|condition: &PaymentCondition| condition.Code.trim().to_string()),
+    )
+    .await
+}
+
+/// Definition of a custom field, as listed by the division's metadata endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct CustomFieldDefinition {
+    ID: String,
+    Code: Option<String>,
+    Description: Option<String>,
+}
+
+impl CustomFieldDefinition {
+    /// Name of the column the field's value is expanded into
+    fn column(&self) -> String {
+        let name = [&self.Description, &self.Code]
+            .into_iter()
+            .flatten()
+            .map(|name| name.trim())
+            .find(|name| !name.is_empty())
+            .unwrap_or(self.ID.trim());
+        format!("CustomField_{}", name)
+    }
+}
+
+const CUSTOM_FIELD_DEFINITIONS_ENDPOINT: &str = "system/CustomFieldDefinitions";
+
+/// Custom field definitions of a division, cached until the division changes.
+/// A division without custom fields (or without the endpoint) yields an empty list.
+async fn custom_field_definitions(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<CustomFieldDefinition>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: CUSTOM_FIELD_DEFINITIONS_ENDPOINT.to_string(),
+        select: Some(["ID", "Code", "Description"].iter().map(|f| f.to_string()).collect()),
+        ..Default::default()
+    };
+    match cached_lookup(app, operation_id, &request, cancel_flag, |state| &mut state.custom_fields, division, None)
+        .await
+    {
+        Err(e) if e.starts_with("API error (404") => {
+            let mut state_guard = get_app_state().await?;
+            let state = state_guard.as_mut().ok_or("State not initialized")?;
+            state.custom_fields.insert(division, Vec::new());
+            Ok(Vec::new())
+        }
+        result => result,
+    }
+}
+
+/// Expands each row's `CustomField` into one `CustomField_<name>` column per field,
+/// named after the field's description (or code, or ID when both are blank).
+/// `CustomField` holds an object keyed by field ID or code, possibly as a JSON
+/// string; other values are left as they are. The original column is kept.
+async fn expand_custom_fields(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    rows: &mut [Transaction],
+    cancel_flag: &AtomicBool,
+) -> Result<(), String> {
+    if rows.iter().all(|row| is_blank(row.data.get("CustomField"))) {
+        return Ok(());
+    }
+    let definitions = custom_field_definitions(app, operation_id, division, cancel_flag).await?;
+    if definitions.is_empty() {
+        return Ok(());
+    }
+    let mut columns = HashMap::new();
+    for definition in &definitions {
+        columns.insert(definition.ID.trim().to_lowercase(), definition.column());
+        if let Some(code) = &definition.Code {
+            columns.insert(code.trim().to_lowercase(), definition.column());
+        }
+    }
+
+    for row in rows.iter_mut() {
+        let fields = match row.data.get("CustomField") {
+            Some(serde_json::Value::Object(fields)) => fields.clone(),
+            Some(serde_json::Value::String(text)) => match serde_json::from_str(text) {
+                Ok(serde_json::Value::Object(fields)) => fields,
+                _ => continue,
+            },
+            _ => continue,
+        };
+        for (key, value) in fields {
+            if let Some(column) = columns.get(&key.trim().to_lowercase()) {
+                row.data.insert(column.clone(), value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A user of a division, as named in `CreatorFullName` / `ModifierFullName`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+struct ExactUser {
+    UserID: String,
+    FullName: Option<String>,
+    Email: Option<String>,
+}
+
+const USERS_ENDPOINT: &str = "users/Users";
+
+/// Users of a division, sorted by full name and cached until the division changes
+#[tauri::command]
+async fn get_users(app: tauri::AppHandle, division: i32) -> Result<Vec<ExactUser>, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = users(&app, &operation_id, division, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_users", result).await
+}
+
+async fn users(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<ExactUser>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: USERS_ENDPOINT.to_string(),
+        select: Some(["UserID", "FullName", "Email"].iter().map(|f| f.to_string()).collect()),
+        ..Default::default()
+    };
+    cached_lookup(
+        app,
+        operation_id,
+        &request,
+        cancel_flag,
+        |state| &mut state.users,
+        division,
+        Some(|user: &ExactUser| user.FullName.as_deref().unwrap_or("").trim().to_lowercase()),
+    )
+    .await
+}
+
+/// Fills `CostCenterDescription` / `CostUnitDescription` from the division's lists,
+/// fetching a list only when some row is missing its description
+async fn fill_cost_descriptions(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    rows: &mut [Transaction],
+    cancel_flag: &AtomicBool,
+) -> Result<(), String> {
+    let lists = [
+        (COST_CENTERS_ENDPOINT, "CostCenter", "CostCenterDescription"),
+        (COST_UNITS_ENDPOINT, "CostUnit", "CostUnitDescription"),
+    ];
+    for (endpoint, code_field, description_field) in lists {
+        let missing = rows.iter().any(|row| {
+            !is_blank(row.data.get(code_field)) && is_blank(row.data.get(description_field))
+        });
+        if !missing {
+            continue;
+        }
+        let descriptions: HashMap<String, String> = code_list(app, operation_id, division, endpoint, cancel_flag)
+            .await?
+            .into_iter()
+            .filter_map(|code| Some((code.Code.trim().to_string(), code.Description?)))
+            .collect();
+        fill_descriptions(rows, code_field, description_field, &descriptions);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct DocumentInfo {
+    id: String,
+    subject: Option<String>,
+    document_type: Option<i64>,
+    type_description: Option<String>,
+    document_date: Option<serde_json::Value>,
+    attachment_count: usize,
+    attachments: Vec<DocumentAttachment>,
+}
+
+#[derive(Debug, Serialize)]
+struct DocumentAttachment {
+    id: String,
+    file_name: Option<String>,
+    file_size: Option<f64>,
+}
+
+/// Filters are built from these ids, so anything that isn't a GUID is rejected
+fn validate_guid(id: &str) -> Result<&str, String> {
+    let id = id.trim().trim_start_matches('{').trim_end_matches('}');
+    let valid = id.len() == 36
+        && id.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+    if valid {
+        Ok(id)
+    } else {
+        Err(format!("Not a valid id: {}", id))
+    }
+}
+
+/// Runs a small side lookup (no progress events) and returns the raw rows
+async fn lookup_rows(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    endpoint: &str,
+    fields: &[&str],
+    filter: String,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<HashMap<String, serde_json::Value>>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint: endpoint.to_string(),
+        select: Some(fields.iter().map(|f| f.to_string()).collect()),
+        filter: Some(filter),
+        silent: true,
+        ..Default::default()
+    };
+    let rows = fetch_collection(app, operation_id, &request, cancel_flag).await?;
+    Ok(rows.into_iter().map(|row| row.data).collect())
+}
+
+/// Subject, type and attachments of the document a transaction's `Document` field
+/// points to, from `documents/Documents` and `documents/DocumentAttachments`
+#[tauri::command]
+async fn get_document_info(
+    app: tauri::AppHandle,
+    division: i32,
+    document_id: String,
+) -> Result<DocumentInfo, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = document_info(&app, &operation_id, division, &document_id, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_document_info", result).await
+}
+
+async fn document_info(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    document_id: &str,
+    cancel_flag: &AtomicBool,
+) -> Result<DocumentInfo, String> {
+    let document_id = validate_guid(document_id)?;
+    let mut documents = lookup_rows(
+        app,
+        operation_id,
+        division,
+        "documents/Documents",
+        &["ID", "Subject", "Type", "TypeDescription", "DocumentDate"],
+        format!("ID eq guid'{}'", document_id),
+        cancel_flag,
+    )
+    .await?;
+    let Some(mut document) = documents.pop() else {
+        return Err(format!("Document {} not found", document_id));
+    };
+
+    let attachments: Vec<DocumentAttachment> = lookup_rows(
+        app,
+        operation_id,
+        division,
+        "documents/DocumentAttachments",
+        &["ID", "FileName", "FileSize"],
+        format!("Document eq guid'{}'", document_id),
+        cancel_flag,
+    )
+    .await?
+    .into_iter()
+    .map(|row| DocumentAttachment {
+        id: row.get("ID").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+        file_name: row.get("FileName").and_then(|v| v.as_str()).map(String::from),
+        file_size: row.get("FileSize").and_then(|v| v.as_f64()),
+    })
+    .collect();
+
+    let text = |value: Option<serde_json::Value>| value.and_then(|v| v.as_str().map(String::from));
+    Ok(DocumentInfo {
+        id: document_id.to_string(),
+        subject: text(document.remove("Subject")),
+        document_type: document.remove("Type").and_then(|v| v.as_i64()),
+        type_description: text(document.remove("TypeDescription")),
+        document_date: document.remove("DocumentDate").filter(|v| !v.is_null()),
+        attachment_count: attachments.len(),
+        attachments,
+    })
+}
+
+/// Saves one document attachment to `path` and returns the number of bytes written
+#[tauri::command]
+async fn download_document_attachment(
+    app: tauri::AppHandle,
+    division: i32,
+    attachment_id: String,
+    path: String,
+) -> Result<usize, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = download_attachment(&app, &operation_id, division, &attachment_id, &path, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("download_document_attachment", result).await
+}
+
+async fn download_attachment(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    attachment_id: &str,
+    path: &str,
+    cancel_flag: &AtomicBool,
+) -> Result<usize, String> {
+    let attachment_id = validate_guid(attachment_id)?;
+    let attachment = lookup_rows(
+        app,
+        operation_id,
+        division,
+        "documents/DocumentAttachments",
+        &["ID", "Url"],
+        format!("ID eq guid'{}'", attachment_id),
+        cancel_flag,
+    )
+    .await?
+    .pop()
+    .ok_or(format!("Attachment {} not found", attachment_id))?;
+    let url = attachment
+        .get("Url")
+        .and_then(|v| v.as_str())
+        .filter(|url| !url.is_empty())
+        .ok_or(format!("Attachment {} has no file", attachment_id))?
+        .to_string();
+
+    let session = {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        state.refresh_token().await?;
+        state.session()?
+    };
+    check_cancelled(cancel_flag)?;
+    let bytes = session.download(&url).await?;
+    fs::write(path, &bytes).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(bytes.len())
+}
+
+/// Exact publishes `$metadata` per service; transaction lines live in this one
+const DEFAULT_METADATA_SERVICE: &str = "financialtransaction";
+
+/// The OData `$metadata` XML of one of a division's services (`financialtransaction`
+/// unless given), for discovering which fields an endpoint has. The document is
+/// cached in `~/.exact_gui/metadata/` and only fetched again with `refresh`.
+#[tauri::command]
+async fn get_odata_metadata(
+    division: i32,
+    service: Option<String>,
+    refresh: Option<bool>,
+) -> Result<String, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let service = service.unwrap_or_else(|| DEFAULT_METADATA_SERVICE.to_string());
+    let result = odata_metadata(&operation_id, division, &service, refresh.unwrap_or(false), &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("get_odata_metadata", result).await
+}
+
+async fn odata_metadata(
+    operation_id: &str,
+    division: i32,
+    service: &str,
+    refresh: bool,
+    cancel_flag: &AtomicBool,
+) -> Result<String, String> {
+    let service = service.trim().trim_matches('/');
+    if service.is_empty() || !service.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("Invalid service name: {}", service));
+    }
+    let cache_dir = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        state.data_dir.join("metadata")
+    };
+    let cache_file = cache_dir.join(format!("{}_{}.xml", division, service.to_lowercase()));
+    if !refresh {
+        match fs::read_to_string(&cache_file) {
+            Ok(xml) => return Ok(xml),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("[FETCH {}] Ignoring unreadable {}: {}", operation_id, cache_file.display(), e),
+        }
+    }
+
+    let session = {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        state.refresh_token().await?;
+        state.session()?
+    };
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::ACCEPT,
+        reqwest::header::HeaderValue::from_static("application/xml"),
+    );
+    let path = format!("/v1/{}/{}/$metadata", division, service);
+    let (mut response, _slot) = session.cancellable(session.send_with(&path, headers), cancel_flag).await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("API error ({}): {}", status, body));
+    }
+
+    // The document runs to megabytes; it goes to disk chunk by chunk and is read
+    // back once, instead of being buffered next to its decoded copy
+    fs::create_dir_all(&cache_dir).map_err(|e| format!("Failed to create {}: {}", cache_dir.display(), e))?;
+    let partial_file = cache_file.with_extension("xml.partial");
+    let write_error = |e: std::io::Error| format!("Failed to write {}: {}", partial_file.display(), e);
+    let mut writer = std::io::BufWriter::new(fs::File::create(&partial_file).map_err(write_error)?);
+    let written = async {
+        while let Some(chunk) = session
+            .cancellable(
+                async { response.chunk().await.map_err(|e| format!("Failed to read response: {}", e)) },
+                cancel_flag,
+            )
+            .await?
+        {
+            std::io::Write::write_all(&mut writer, &chunk).map_err(write_error)?;
+        }
+        std::io::Write::flush(&mut writer).map_err(write_error)
+    }
+    .await;
+    drop(writer);
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial_file);
+        return Err(e);
+    }
+    fs::rename(&partial_file, &cache_file)
+        .map_err(|e| format!("Failed to save {}: {}", cache_file.display(), e))?;
+    eprintln!("[FETCH {}] Cached $metadata of {} for division {}", operation_id, service, division);
+    fs::read_to_string(&cache_file).map_err(|e| format!("Failed to read {}: {}", cache_file.display(), e))
+}
+
+#[derive(Debug, Serialize)]
+struct SyncResult {
+    records: Vec<serde_json::Value>,
+    /// Highest `Timestamp` seen, pass it as `from_timestamp` for the next delta
+    next_timestamp: i64,
+}
+
+/// Incremental pull through Exact's `/sync/` endpoints, which page by the row
+/// `Timestamp` rather than `Modified`. Without `from_timestamp` the cursor
+/// persisted by the previous sync of this division/endpoint is used.
+#[tauri::command]
+async fn sync_endpoint(
+    app: tauri::AppHandle,
+    division: i32,
+    endpoint: String,
+    from_timestamp: Option<i64>,
+    select: Option<Vec<String>>,
+) -> Result<SyncResult, String> {
+    let endpoint = if endpoint.starts_with("sync/") {
+        endpoint
+    } else {
+        format!("sync/{}", endpoint)
+    };
+    let cursor_key = format!("{}/{}", division, endpoint);
+
+    let from_timestamp = match from_timestamp {
+        Some(timestamp) => timestamp,
+        None => {
+            let state_guard = get_app_state().await?;
+            let state = state_guard.as_ref().ok_or("State not initialized")?;
+            state.load_sync_cursors().get(&cursor_key).copied().unwrap_or(1)
+        }
+    };
+
+    let mut fields = match select {
+        Some(fields) if !fields.is_empty() => fields,
+        _ if endpoint == "sync/Financial/TransactionLines" => {
+            TRANSACTION_FIELDS.split(',').map(String::from).collect()
+        }
+        _ => return record_errors("sync_endpoint", Err(format!("A select list is required for {}", endpoint))).await,
+    };
+    if !fields.iter().any(|f| f == "Timestamp") {
+        fields.push("Timestamp".to_string());
+    }
+
+    let request = FetchRequest {
+        division,
+        endpoint,
+        select: Some(fields),
+        filter: Some(format!("Timestamp gt {}", from_timestamp)),
+        ..Default::default()
+    };
+    let records = fetch_records("sync_endpoint", &app, request).await?;
+
+    let next_timestamp = records
+        .iter()
+        .filter_map(|r| r.get("Timestamp").and_then(parse_count))
+        .max()
+        .unwrap_or(from_timestamp);
+
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    state.save_sync_cursor(&cursor_key, next_timestamp)?;
+
+    Ok(SyncResult {
+        records,
+        next_timestamp,
+    })
+}
+
+/// Stop signals of the running `start_watch` tasks by watch id
+static WATCHES: Mutex<BTreeMap<String, Arc<Notify>>> = Mutex::const_new(BTreeMap::new());
+static NEXT_WATCH_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Shortest interval a watcher polls at, to stay well inside Exact's rate limit
+const MIN_WATCH_INTERVAL_SECS: u64 = 30;
+
+const SYNC_TRANSACTION_LINES_ENDPOINT: &str = "sync/Financial/TransactionLines";
+
+/// Polls the transaction lines of a division every `interval_secs` through the
+/// sync endpoint and emits `new-transactions` with only the rows changed since the
+/// previous poll. The first poll starts from the division's persisted sync cursor.
+/// Polling is skipped while logged out and waits out rate limiting. Returns the
+/// watch id for `stop_watch`.
+#[tauri::command]
+async fn start_watch(
+    app: tauri::AppHandle,
+    division: i32,
+    filter: Option<String>,
+    interval_secs: u64,
+) -> Result<String, String> {
+    let from_timestamp = {
+        let state_guard = record_errors("start_watch", get_app_state().await).await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        state
+            .load_sync_cursors()
+            .get(&format!("{}/{}", division, SYNC_TRANSACTION_LINES_ENDPOINT))
+            .copied()
+            .unwrap_or(1)
+    };
+    let id = format!("watch-{}", NEXT_WATCH_ID.fetch_add(1, Ordering::Relaxed));
+    let stop = Arc::new(Notify::new());
+    WATCHES.lock().await.insert(id.clone(), stop.clone());
+
+    let interval = std::time::Duration::from_secs(interval_secs.max(MIN_WATCH_INTERVAL_SECS));
+    let watch_id = id.clone();
+    tauri::async_runtime::spawn(async move {
+        eprintln!("[WATCH {}] Watching division {} every {:?}", watch_id, division, interval);
+        let mut cursor = from_timestamp;
+        let mut wait = std::time::Duration::ZERO;
+        loop {
+            tokio::select! {
+                _ = stop.notified() => break,
+                _ = tokio::time::sleep(wait) => {}
+            }
+            wait = interval;
+
+            let authenticated = match get_app_state().await {
+                Ok(state) => state.as_ref().is_some_and(|s| s.access_token.is_some()),
+                Err(_) => false,
+            };
+            if !authenticated {
+                continue;
+            }
+
+            let result = poll_watch(&app, division, filter.as_deref(), cursor).await;
+            match record_errors("start_watch", result).await {
+                Ok((transactions, next_timestamp)) => {
+                    cursor = next_timestamp;
+                    if !transactions.is_empty() {
+                        eprintln!("[WATCH {}] {} new or changed rows", watch_id, transactions.len());
+                        let _ = app.emit("new-transactions", serde_json::json!({
+                            "watch_id": watch_id,
+                            "division": division,
+                            "transactions": transactions,
+                            "next_timestamp": next_timestamp
+                        }));
+                    }
+                }
+                Err(e) => {
+                    if let Some(secs) = e.strip_prefix(RATE_LIMITED_ERROR).and_then(|s| s.trim().parse::<u64>().ok()) {
+                        wait = wait.max(std::time::Duration::from_secs(secs));
+                    }
+                    eprintln!("[WATCH {}] Poll failed: {}", watch_id, e);
+                }
+            }
+        }
+        eprintln!("[WATCH {}] Stopped", watch_id);
+    });
+    Ok(id)
+}
+
+async fn poll_watch(
+    app: &tauri::AppHandle,
+    division: i32,
+    filter: Option<&str>,
+    from_timestamp: i64,
+) -> Result<(Vec<serde_json::Value>, i64), String> {
+    let mut fields: Vec<String> = TRANSACTION_FIELDS.split(',').map(String::from).collect();
+    fields.push("Timestamp".to_string());
+    let timestamp_filter = format!("Timestamp gt {}", from_timestamp);
+    let request = FetchRequest {
+        division,
+        endpoint: SYNC_TRANSACTION_LINES_ENDPOINT.to_string(),
+        select: Some(fields),
+        filter: Some(match filter {
+            Some(f) if !f.trim().is_empty() => format!("({}) and {}", f, timestamp_filter),
+            _ => timestamp_filter,
+        }),
+        silent: true,
+        ..Default::default()
+    };
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = fetch_collection_pages(app, &operation_id, &request, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+
+    let records: Vec<serde_json::Value> = result?
+        .into_iter()
+        .map(|row| serde_json::Value::Object(row.data.into_iter().collect()))
+        .collect();
+    let next_timestamp = records
+        .iter()
+        .filter_map(|r| r.get("Timestamp").and_then(parse_count))
+        .max()
+        .unwrap_or(from_timestamp);
+    Ok((records, next_timestamp))
+}
+
+/// Ends a watcher started by `start_watch` before its next poll
+#[tauri::command]
+async fn stop_watch(id: String) -> Result<(), String> {
+    let stop = WATCHES.lock().await.remove(&id);
+    match stop {
+        // notify_one stores a permit, so a watcher mid-poll stops right after it
+        Some(stop) => {
+            stop.notify_one();
+            Ok(())
+        }
+        None => record_errors("stop_watch", Err(format!("No running watch {}", id))).await,
+    }
+}
+
+/// Label of the fetch started by `restart_transactions`, so the next restart finds it
+const RESTART_LABEL: &str = "restart-transactions";
+
+/// Cancels the fetch started by the previous restart, waits until it has actually
+/// stopped and then starts a fresh transaction fetch in the background. Other
+/// operations (backups, exports, watches, ...) keep running. The results are
+/// delivered through the `transactions-complete` / `transactions-failed` events,
+/// tagged with the returned operation id.
+#[tauri::command]
+async fn restart_transactions(
+    app: tauri::AppHandle,
+    division: i32,
+    filter: Option<String>,
+    raw_dates: Option<bool>,
+) -> Result<String, String> {
+    let previous = {
+        let operations = OPERATIONS.lock().await;
+        let labels = OPERATION_LABELS.lock().await;
+        labels
+            .get(RESTART_LABEL)
+            .and_then(|id| operations.get(id).map(|operation| (id.clone(), operation)))
+            .map(|(id, operation)| {
+                operation.cancel_flag.store(true, Ordering::Relaxed);
+                (id, operation.finished.clone())
+            })
+    };
+    if let Some((previous_id, finished)) = previous {
+        eprintln!("[FETCH {}] Cancelled by restart", previous_id);
+        finished.notified().await;
+    }
+
+    let request = FetchRequest {
+        raw_dates: raw_dates.unwrap_or(false),
+        ..FetchRequest::transaction_lines(division, filter)
+    };
+    let (operation_id, cancel_flag) = match begin_labeled_operation(Some(RESTART_LABEL.to_string())).await {
+        Ok(operation) => operation,
+        // Another restart got in while the previous fetch was stopping
+        Err(e) => return record_errors("restart_transactions", Err(e)).await,
+    };
+    let id = operation_id.clone();
+
+    tauri::async_runtime::spawn(async move {
+        let result = fetch_collection(&app, &operation_id, &request, &cancel_flag).await;
+        finish_operation(&operation_id).await;
+        remember_transactions(Some(request.division), &result).await;
+        match record_errors("restart_transactions", result).await {
+            Ok(transactions) => {
+                let _ = app.emit("transactions-complete", serde_json::json!({
+                    "operation_id": operation_id,
+                    "transactions": transactions
+                }));
+            }
+            Err(e) => {
+                let _ = app.emit("transactions-failed", serde_json::json!({
+                    "operation_id": operation_id,
+                    "message": e
+                }));
+            }
+        }
+    });
+
+    Ok(id)
+}
+
+/// Fetches any entity collection with an optional `$select`, `$filter` and `$expand`.
+/// Expanded navigation properties are kept as nested objects in the result rows.
+#[tauri::command]
+async fn fetch_entity(
+    app: tauri::AppHandle,
+    division: i32,
+    endpoint: String,
+    select: Option<Vec<String>>,
+    filter: Option<String>,
+    expand: Option<Vec<String>>,
+    raw_dates: Option<bool>,
+) -> Result<Vec<Transaction>, String> {
+    let request = FetchRequest {
+        division,
+        endpoint,
+        select,
+        filter,
+        expand,
+        raw_dates: raw_dates.unwrap_or(false),
+        ..Default::default()
+    };
+    let result = fetch_collection_once(&app, &request, None).await;
+    record_errors("fetch_entity", result).await
+}
+
+/// A fetch that streams its rows to an NDJSON file. The descriptor is written to
+/// `~/.exact_gui/resumable/<id>.json` after every page, so when the app is closed
+/// mid-fetch the export can be continued from the next page after a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ResumableFetch {
+    id: String,
+    request: FetchRequest,
+    /// The finished file; rows are appended to `<destination>.partial` until the last page
+    destination: PathBuf,
+    /// Page to continue from, `None` before the first page was written
+    next_path: Option<String>,
+    rows_written: usize,
+    /// Length of the partial file matching `next_path`; anything after it is a page
+    /// that was written but not recorded, and is dropped on resume
+    partial_len: u64,
+    complete: bool,
+    started_at: String,
+    updated_at: String,
+}
+
+impl ResumableFetch {
+    fn partial_file(&self) -> PathBuf {
+        let mut name = self.destination.clone().into_os_string();
+        name.push(".partial");
+        PathBuf::from(name)
+    }
+}
+
+fn save_resumable(dir: &Path, fetch: &ResumableFetch) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create resumable dir: {}", e))?;
+    fs::write(
+        dir.join(format!("{}.json", fetch.id)),
+        serde_json::to_string_pretty(fetch).unwrap(),
+    )
+    .map_err(|e| format!("Failed to save resumable fetch: {}", e))
+}
+
+fn load_resumable(dir: &Path, id: &str) -> Result<ResumableFetch, String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("Unknown resumable operation: {}", id));
+    }
+    let content = fs::read_to_string(dir.join(format!("{}.json", id)))
+        .map_err(|_| format!("Unknown resumable operation: {}", id))?;
+    serde_json::from_str(&content).map_err(|e| format!("Failed to parse resumable fetch: {}", e))
+}
+
+fn remove_resumable(dir: &Path, id: &str) {
+    let _ = fs::remove_file(dir.join(format!("{}.json", id)));
+}
+
+/// Fetches a collection straight into an NDJSON file (one row object per line),
+/// without keeping the rows in memory. If it is interrupted it shows up in
+/// `list_resumable_operations` and can be continued with `resume_persisted_fetch`.
+#[tauri::command]
+async fn fetch_to_file(
+    app: tauri::AppHandle,
+    division: i32,
+    endpoint: String,
+    select: Option<Vec<String>>,
+    filter: Option<String>,
+    raw_dates: Option<bool>,
+    path: String,
+) -> Result<usize, String> {
+    let now = chrono::Utc::now();
+    let fetch = ResumableFetch {
+        id: format!("fetch-{}", now.timestamp_millis()),
+        request: FetchRequest {
+            division,
+            endpoint,
+            select,
+            filter,
+            raw_dates: raw_dates.unwrap_or(false),
+            ..Default::default()
+        },
+        destination: PathBuf::from(path),
+        next_path: None,
+        rows_written: 0,
+        partial_len: 0,
+        complete: false,
+        started_at: now.to_rfc3339(),
+        updated_at: now.to_rfc3339(),
+    };
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = run_resumable_fetch(&app, &operation_id, fetch, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("fetch_to_file", result).await
+}
+
+/// Interrupted file fetches, oldest first. Entries not updated within
+/// `resumable_max_age_hours` are deleted together with their partial file.
+#[tauri::command]
+async fn list_resumable_operations() -> Result<Vec<ResumableFetch>, String> {
+    record_errors("list_resumable_operations", resumable_fetches().await).await
+}
+
+async fn resumable_fetches() -> Result<Vec<ResumableFetch>, String> {
+    let (dir, max_age_hours) = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        (
+            state.data_dir.join("resumable"),
+            state.config.resumable_max_age_hours.unwrap_or(72),
+        )
+    };
+    let cutoff = chrono::Utc::now() - chrono::Duration::hours(max_age_hours);
+    let active = ACTIVE_RESUMABLE_FETCHES.lock().await;
+
+    let mut fetches = Vec::new();
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(fetches);
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") || active.contains(id) {
+            continue;
+        }
+        let fetch = match load_resumable(&dir, id) {
+            Ok(fetch) => fetch,
+            Err(_) => {
+                remove_resumable(&dir, id);
+                continue;
+            }
+        };
+        let stale = chrono::DateTime::parse_from_rfc3339(&fetch.updated_at)
+            .map(|updated| updated < cutoff)
+            .unwrap_or(true);
+        if stale {
+            let _ = fs::remove_file(fetch.partial_file());
+            remove_resumable(&dir, &fetch.id);
+        } else {
+            fetches.push(fetch);
+        }
+    }
+    fetches.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+    Ok(fetches)
+}
+
+/// Continues an interrupted `fetch_to_file` from its last recorded page, appending
+/// to the same partial file, and moves it into place once the last page is in.
+#[tauri::command]
+async fn resume_persisted_fetch(app: tauri::AppHandle, id: String) -> Result<usize, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = resume_fetch(&app, &operation_id, &id, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("resume_persisted_fetch", result).await
+}
+
+async fn resume_fetch(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    id: &str,
+    cancel_flag: &AtomicBool,
+) -> Result<usize, String> {
+    let fetch = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        load_resumable(&state.data_dir.join("resumable"), id)?
+    };
+    run_resumable_fetch(app, operation_id, fetch, cancel_flag).await
+}
+
+async fn run_resumable_fetch(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    fetch: ResumableFetch,
+    cancel_flag: &AtomicBool,
+) -> Result<usize, String> {
+    if !ACTIVE_RESUMABLE_FETCHES.lock().await.insert(fetch.id.clone()) {
+        return Err(format!("Resumable operation {} is already running", fetch.id));
+    }
+    let id = fetch.id.clone();
+    let result = write_resumable_fetch(app, operation_id, fetch, cancel_flag).await;
+    ACTIVE_RESUMABLE_FETCHES.lock().await.remove(&id);
+    if let Err(e) = &result {
+        eprintln!("[FETCH {}] Failed: {}", operation_id, e);
+        handle_division_access_lost(app, operation_id, e).await;
+    }
+    result
+}
+
+async fn write_resumable_fetch(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    mut fetch: ResumableFetch,
+    cancel_flag: &AtomicBool,
+) -> Result<usize, String> {
+    use std::io::{Seek, Write};
+
+    let dir = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        state.data_dir.join("resumable")
+    };
+    save_resumable(&dir, &fetch)?;
+
+    let partial_file = fetch.partial_file();
+    if !fetch.complete {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&partial_file)
+            .map_err(|e| format!("Failed to open {}: {}", partial_file.display(), e))?;
+        file.set_len(fetch.partial_len)
+            .map_err(|e| format!("Failed to truncate {}: {}", partial_file.display(), e))?;
+        let mut writer = std::io::BufWriter::new(file);
+        writer
+            .seek(std::io::SeekFrom::End(0))
+            .map_err(|e| format!("Failed to seek {}: {}", partial_file.display(), e))?;
+
+        let request = fetch.request.clone();
+        let resume_from = fetch.next_path.clone().map(|next_path| ResumePoint {
+            next_path,
+            rows: fetch.rows_written,
+        });
+        fetch_pages(app, operation_id, &request, cancel_flag, resume_from, |rows, next_path| {
+            let write_error = |e: std::io::Error| format!("Failed to write {}: {}", partial_file.display(), e);
+            fetch.rows_written += rows.len();
+            for row in rows {
+                serde_json::to_writer(&mut writer, &row.data)
+                    .map_err(|e| format!("Failed to write {}: {}", partial_file.display(), e))?;
+                writer.write_all(b"\n").map_err(write_error)?;
+            }
+            writer.flush().map_err(write_error)?;
+            fetch.partial_len = writer.stream_position().map_err(write_error)?;
+            fetch.next_path = next_path.map(String::from);
+            fetch.complete = next_path.is_none();
+            fetch.updated_at = chrono::Utc::now().to_rfc3339();
+            save_resumable(&dir, &fetch)
+        })
+        .await?;
+    }
+
+    fs::rename(&partial_file, &fetch.destination)
+        .map_err(|e| format!("Failed to move {} into place: {}", partial_file.display(), e))?;
+    remove_resumable(&dir, &fetch.id);
+    Ok(fetch.rows_written)
+}
+
+/// What `backup_division` can capture: name (also the file name), endpoint and the
+/// fields selected. `None` selects every field the endpoint registry knows.
+const BACKUP_ENDPOINTS: &[(&str, &str, Option<&[&str]>)] = &[
+    (
+        "gl_accounts",
+        "financial/GLAccounts",
+        Some(&[
+            "ID", "Code", "Description", "Type", "TypeDescription", "BalanceSide", "BalanceType",
+            "IsBlocked", "VATCode", "Created", "Modified",
+        ]),
+    ),
+    (
+        "accounts",
+        "crm/Accounts",
+        Some(&[
+            "ID", "Code", "Name", "AddressLine1", "Postcode", "City", "Country", "Email", "Phone",
+            "VATNumber", "ChamberOfCommerce", "Status", "IsSupplier", "Blocked", "Created", "Modified",
+        ]),
+    ),
+    ("transactions", TRANSACTION_LINES_ENDPOINT, None),
+    ("vat_codes", "vat/VATCodes", None),
+    ("journals", JOURNALS_ENDPOINT, None),
+];
+
+#[derive(Debug, Clone, Serialize)]
+struct BackupFile {
+    name: String,
+    endpoint: String,
+    file: String,
+    rows: usize,
+}
+
+/// `manifest.json` of a backup, rewritten after every endpoint so an interrupted
+/// backup still says what it holds
+#[derive(Debug, Clone, Serialize)]
+struct BackupManifest {
+    division: i32,
+    division_name: Option<String>,
+    app_version: String,
+    api_base: String,
+    started_at: String,
+    finished_at: Option<String>,
+    complete: bool,
+    files: Vec<BackupFile>,
+}
+
+/// Sleeps for `duration`, returning early with an error once the operation is cancelled
+async fn sleep_cancellable(
+    duration: std::time::Duration,
+    cancel_flag: &AtomicBool,
+    check_interval: std::time::Duration,
+) -> Result<(), String> {
+    let deadline = tokio::time::Instant::now() + duration;
+    while tokio::time::Instant::now() < deadline {
+        check_cancelled(cancel_flag)?;
+        tokio::time::sleep_until(deadline.min(tokio::time::Instant::now() + check_interval)).await;
+    }
+    check_cancelled(cancel_flag)
+}
+
+/// `fetch_pages` for long running exports that shouldn't fail on a busy API: a
+/// rate-limited page is waited out (spending the operation's retry budget) and the
+/// fetch continues after the last page handed to `on_page`
+async fn fetch_pages_waiting<F>(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    request: &FetchRequest,
+    cancel_flag: &AtomicBool,
+    mut on_page: F,
+) -> Result<usize, String>
+where
+    F: FnMut(Vec<Transaction>, Option<&str>) -> Result<(), String>,
+{
+    let (retry_budget, check_interval) = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        (
+            state.config.retry_budget.unwrap_or(20),
+            std::time::Duration::from_millis(state.config.cancel_check_interval_ms.unwrap_or(100).max(1)),
+        )
+    };
+    let mut rows = 0;
+    let mut last_next: Option<String> = None;
+    let mut resume_from = None;
+    loop {
+        let result = fetch_pages(app, operation_id, request, cancel_flag, resume_from.take(), |page, next_path| {
+            let count = page.len();
+            on_page(page, next_path)?;
+            rows += count;
+            last_next = next_path.map(String::from);
+            Ok(())
+        })
+        .await;
+        match result {
+            Err(e) if e.starts_with(RATE_LIMITED_ERROR) => {
+                spend_retry(operation_id, retry_budget).await?;
+                let wait = e[RATE_LIMITED_ERROR.len()..].trim().parse::<u64>().unwrap_or(5);
+                eprintln!("[FETCH {}] Rate limited, waiting {}s", operation_id, wait);
+                sleep_cancellable(std::time::Duration::from_secs(wait.clamp(1, 60)), cancel_flag, check_interval)
+                    .await?;
+                // Without a page handed over yet the fetch simply starts over
+                resume_from = last_next.clone().map(|next_path| ResumePoint { next_path, rows });
+            }
+            result => return result.map(|fetched| fetched.rows),
+        }
+    }
+}
+
+/// Exports a division's core data into a new `backup-<division>-<timestamp>` folder
+/// under `path`: one NDJSON file per endpoint in `backup_endpoints` plus a
+/// `manifest.json`. Emits `backup-progress` after every page. A rate-limited page
+/// is waited out and the endpoint continues where it stopped.
+#[tauri::command]
+async fn backup_division(app: tauri::AppHandle, division: i32, path: String) -> Result<BackupManifest, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = run_backup(&app, &operation_id, division, Path::new(&path), &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    if let Err(e) = &result {
+        handle_division_access_lost(&app, &operation_id, e).await;
+    }
+    record_errors("backup_division", result).await
+}
+
+async fn run_backup(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    path: &Path,
+    cancel_flag: &AtomicBool,
+) -> Result<BackupManifest, String> {
+    use std::io::Write;
+
+    let (names, mut manifest) = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        let manifest = BackupManifest {
+            division,
+            division_name: state.division_names.get(&division).cloned(),
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            api_base: state.api.clone(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+            finished_at: None,
+            complete: false,
+            files: Vec::new(),
+        };
+        (state.config.backup_endpoints.clone(), manifest)
+    };
+    let endpoints: Vec<_> = match names {
+        Some(names) => names
+            .iter()
+            .map(|name| {
+                BACKUP_ENDPOINTS.iter().find(|(n, _, _)| n == name).ok_or_else(|| {
+                    let known: Vec<&str> = BACKUP_ENDPOINTS.iter().map(|(n, _, _)| *n).collect();
+                    format!("Unknown backup endpoint {}, expected one of {}", name, known.join(", "))
+                })
+            })
+            .collect::<Result<_, _>>()?,
+        None => BACKUP_ENDPOINTS.iter().collect(),
+    };
+
+    let folder = path.join(format!(
+        "backup-{}-{}",
+        division,
+        chrono::Utc::now().format("%Y%m%d-%H%M%S")
+    ));
+    fs::create_dir_all(&folder).map_err(|e| format!("Failed to create {}: {}", folder.display(), e))?;
+    let manifest_path = folder.join("manifest.json");
+    let save_manifest = |manifest: &BackupManifest| {
+        fs::write(&manifest_path, serde_json::to_string_pretty(manifest).unwrap())
+            .map_err(|e| format!("Failed to write {}: {}", manifest_path.display(), e))
+    };
+    save_manifest(&manifest)?;
+    eprintln!("[BACKUP {}] Backing up division {} into {}", operation_id, division, folder.display());
+
+    let steps = endpoints.len();
+    let mut total_rows = 0;
+    for (step, (name, endpoint, fields)) in endpoints.into_iter().enumerate() {
+        let select = match fields {
+            Some(fields) => fields.to_vec(),
+            None => selectable_fields(endpoint).unwrap_or_default(),
+        };
+        let request = FetchRequest {
+            division,
+            endpoint: endpoint.to_string(),
+            select: Some(select.into_iter().map(String::from).collect()),
+            silent: true,
+            ..Default::default()
+        };
+        let file_path = folder.join(format!("{}.ndjson", name));
+        let write_error = |e: std::io::Error| format!("Failed to write {}: {}", file_path.display(), e);
+        let mut writer = std::io::BufWriter::new(fs::File::create(&file_path).map_err(write_error)?);
+
+        let mut rows_written = 0;
+        fetch_pages_waiting(app, operation_id, &request, cancel_flag, |rows, _| {
+            for row in &rows {
+                serde_json::to_writer(&mut writer, &row.data).map_err(|e| write_error(e.into()))?;
+                writer.write_all(b"\n").map_err(write_error)?;
+            }
+            writer.flush().map_err(write_error)?;
+            rows_written += rows.len();
+            let _ = app.emit("backup-progress", serde_json::json!({
+                "operation_id": operation_id,
+                "division": division,
+                "endpoint": name,
+                "step": step + 1,
+                "steps": steps,
+                "rows": rows_written,
+                "total_rows": total_rows + rows_written
+            }));
+            Ok(())
+        })
+        .await?;
+
+        total_rows += rows_written;
+        eprintln!("[BACKUP {}] {}: {} rows", operation_id, name, rows_written);
+        manifest.files.push(BackupFile {
+            name: name.to_string(),
+            endpoint: endpoint.to_string(),
+            file: format!("{}.ndjson", name),
+            rows: rows_written,
+        });
+        save_manifest(&manifest)?;
+    }
+
+    manifest.finished_at = Some(chrono::Utc::now().to_rfc3339());
+    manifest.complete = true;
+    save_manifest(&manifest)?;
+    eprintln!("[BACKUP {}] Done, {} rows in {} files", operation_id, total_rows, manifest.files.len());
+    Ok(manifest)
+}
+
+/// Trims a pasted filter and, with `smart_quotes`, turns typographic quotes back
+/// into the straight quotes OData expects. Spacing inside the filter is kept.
+fn normalize_filter(filter: &str, smart_quotes: bool) -> String {
+    let filter = filter.trim();
+    if !smart_quotes {
+        return filter.to_string();
+    }
+    filter
+        .chars()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' => '"',
+            c => c,
+        })
+        .collect()
+}
+
+fn filter_param(request: &FetchRequest) -> String {
+    match &request.filter {
+        Some(f) if !f.trim().is_empty() => format!(
+            "&$filter={}",
+            urlencoding::encode(&normalize_filter(f, request.normalize_quotes))
+        ),
+        _ => String::new(),
+    }
+}
+
+/// Validates a fetch and builds the path of its first page, relative to the API base
+fn collection_path(request: &FetchRequest) -> Result<String, String> {
+    let endpoint = request.endpoint.as_str();
+    validate_endpoint(endpoint)?;
+    let expand = request.expand.clone().unwrap_or_default();
+    validate_expand(endpoint, &expand)?;
+
+    // Only a caller's own select is checked; the defaults come from the registry
+    let mut attributes = match &request.select {
+        Some(fields) if !fields.is_empty() => {
+            validate_select(endpoint, fields)?;
+            fields.clone()
+        }
+        _ => default_select(endpoint)?,
+    };
+    // OData only returns an expanded navigation property when it is also selected
+    for property in &expand {
+        let root = property.split('/').next().unwrap_or(property).to_string();
+        if !attributes.contains(&root) {
+            attributes.push(root);
+        }
+    }
+
+    let mut path = format!(
+        "/v1/{}/{}?$select={}{}",
+        request.division,
+        endpoint,
+        attributes.join(","),
+        filter_param(request)
+    );
+    if !expand.is_empty() {
+        path.push_str(&format!("&$expand={}", expand.join(",")));
+    }
+    if let Some(orderby) = request.orderby.as_deref().filter(|o| !o.trim().is_empty()) {
+        path.push_str(&format!("&$orderby={}", urlencoding::encode(orderby)));
+    }
+    if let Some(top) = request.top {
+        path.push_str(&format!("&$top={}", top));
+    }
+    Ok(path)
+}
+
+/// Fields requested when a fetch doesn't pass `select`: the usual transaction fields
+/// on the transaction line endpoints, every known field elsewhere. An endpoint
+/// missing from the registry needs an explicit `select`.
+fn default_select(endpoint: &str) -> Result<Vec<String>, String> {
+    match endpoint {
+        TRANSACTION_LINES_ENDPOINT | "financialtransaction/TransactionLines" | SYNC_TRANSACTION_LINES_ENDPOINT => {
+            Ok(TRANSACTION_FIELDS.split(',').map(String::from).collect())
+        }
+        _ => selectable_fields(endpoint)
+            .map(|fields| fields.into_iter().map(String::from).collect())
+            .ok_or_else(|| format!("Endpoint {} needs an explicit select", endpoint)),
+    }
+}
+
+fn count_path(request: &FetchRequest) -> String {
+    format!(
+        "/v1/{}/{}/$count{}",
+        request.division,
+        request.endpoint,
+        filter_param(request).replacen('&', "?", 1)
+    )
+}
+
+/// Rows Exact returns per page: 1000 on the bulk and sync endpoints, 60 elsewhere.
+/// This is also the largest page Exact allows.
+fn default_page_size(endpoint: &str) -> u32 {
+    if endpoint.starts_with("bulk/") || endpoint.starts_with("sync/") {
+        1000
+    } else {
+        60
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FetchCost {
+    estimated_rows: i64,
+    /// Page requests plus the `$count` call itself
+    estimated_requests: i64,
+}
+
+/// Projects how many API calls a fetch would take from a `$count`, without
+/// fetching any rows
+#[tauri::command]
+async fn estimate_fetch_cost(
+    division: i32,
+    endpoint: String,
+    filter: Option<String>,
+    page_size: Option<u32>,
+) -> Result<FetchCost, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let request = FetchRequest {
+        division,
+        endpoint,
+        filter,
+        ..Default::default()
+    };
+    let result = fetch_cost(&request, page_size, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("estimate_fetch_cost", result).await
+}
+
+async fn fetch_cost(
+    request: &FetchRequest,
+    page_size: Option<u32>,
+    cancel_flag: &AtomicBool,
+) -> Result<FetchCost, String> {
+    validate_endpoint(&request.endpoint)?;
+    let (session, page_size) = {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        state.refresh_token().await?;
+        let page_size = page_size
+            .filter(|size| *size > 0)
+            .unwrap_or_else(|| state.config.page_size(&request.endpoint));
+        (state.session()?, page_size)
+    };
+    let response = session.get_cancellable(&count_path(request), cancel_flag).await?;
+    let estimated_rows = parse_count(&response).ok_or("Failed to parse $count response")?;
+    let pages = (estimated_rows.max(0) as u64).div_ceil(page_size.into()).max(1) as i64;
+    Ok(FetchCost {
+        estimated_rows,
+        estimated_requests: pages + 1,
+    })
+}
+
+/// Persists the page size used for an endpoint's fetches when a page has to be
+/// retried smaller and for cost estimates; `None` goes back to Exact's default.
+/// Exact pages server-side, so this can't exceed its own page size.
+#[tauri::command]
+async fn set_endpoint_page_size(endpoint: String, size: Option<u32>) -> Result<(), String> {
+    let result: Result<(), String> = async {
+        validate_endpoint(&endpoint)?;
+        let limit = default_page_size(&endpoint);
+        if let Some(size) = size {
+            if size == 0 || size > limit {
+                return Err(format!("Page size for {} must be between 1 and {}", endpoint, limit));
+            }
+        }
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        let mut config = state.config.clone();
+        let sizes = config.page_sizes.get_or_insert_with(BTreeMap::new);
+        match size {
+            Some(size) => sizes.insert(endpoint, size),
+            None => sizes.remove(&endpoint),
+        };
+        config.save(&state.data_dir)?;
+        state.config = config;
+        Ok(())
+    }
+    .await;
+    record_errors("set_endpoint_page_size", result).await
+}
+
+/// The URL the first page of a fetch would be requested from, without sending it
+#[tauri::command]
+async fn preview_request_url(
+    division: i32,
+    endpoint: String,
+    select: Option<Vec<String>>,
+    filter: Option<String>,
+    orderby: Option<String>,
+    top: Option<u32>,
+) -> Result<String, String> {
+    let request = FetchRequest {
+        division,
+        endpoint,
+        select,
+        filter,
+        orderby,
+        top,
+        ..Default::default()
+    };
+    let result = async {
+        let path = collection_path(&request)?;
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        Ok::<_, String>(format!("{}{}", state.api, path))
+    }
+    .await;
+    record_errors("preview_request_url", result).await
+}
+
+/// Generic paginated fetch for any `/v1/{division}/{endpoint}` collection, with the
+/// same cancellation, progress and date normalization as the transaction fetch.
+/// Without an explicit `select` the endpoint's `default_select` is requested.
+async fn fetch_collection(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    request: &FetchRequest,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<Transaction>, String> {
+    fetch_collection_counted(app, operation_id, request, cancel_flag)
+        .await
+        .map(|(rows, _)| rows)
+}
+
+/// `fetch_collection` that also returns how many pages needed a reduced `$top`
+async fn fetch_collection_counted(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    request: &FetchRequest,
+    cancel_flag: &AtomicBool,
+) -> Result<(Vec<Transaction>, usize), String> {
+    let mut result = fetch_collection_pages_counted(app, operation_id, request, cancel_flag).await;
+    if request.endpoint == TRANSACTION_LINES_ENDPOINT {
+        if let Ok((rows, _)) = &mut result {
+            if let Err(e) = enrich_transactions(app, operation_id, request.division, rows, cancel_flag).await {
+                result = Err(e);
+            }
+        }
+    }
+    if request.endpoint == TRANSACTION_LINES_ENDPOINT && request.expand_custom_fields {
+        if let Ok((rows, _)) = &mut result {
+            match expand_custom_fields(app, operation_id, request.division, rows, cancel_flag).await {
+                Err(e) if e.contains("cancelled") => result = Err(e),
+                Err(e) => eprintln!("[ENRICH {}] Custom field expansion failed: {}", operation_id, e),
+                Ok(()) => {}
+            }
+        }
+    }
+    if let Err(e) = &result {
+        eprintln!("[FETCH {}] Failed: {}", operation_id, e);
+        handle_division_access_lost(app, operation_id, e).await;
+    }
+    result
+}
+
+async fn fetch_collection_pages(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    request: &FetchRequest,
+    cancel_flag: &AtomicBool,
+) -> Result<Vec<Transaction>, String> {
+    fetch_collection_pages_counted(app, operation_id, request, cancel_flag)
+        .await
+        .map(|(rows, _)| rows)
+}
+
+async fn fetch_collection_pages_counted(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    request: &FetchRequest,
+    cancel_flag: &AtomicBool,
+) -> Result<(Vec<Transaction>, usize), String> {
+    let mut all_results = Vec::new();
+    let fetched = fetch_pages(app, operation_id, request, cancel_flag, None, |rows, _| {
+        all_results.extend(rows);
+        Ok(())
+    })
+    .await?;
+    Ok((all_results, fetched.reduced_pages))
+}
+
+/// Where an interrupted fetch continues: the next page to request and how many
+/// rows were already received before it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ResumePoint {
+    next_path: String,
+    rows: usize,
+}
+
+/// The `$count` request of a fetch, retried `retries` times: a rate limit after the
+/// wait Exact asked for, other failures after a short doubling delay
+async fn fetch_count(
+    session: &ApiSession,
+    operation_id: &str,
+    path: &str,
+    retries: u32,
+    cancel_flag: &AtomicBool,
+) -> Result<serde_json::Value, String> {
+    let mut delay = std::time::Duration::from_millis(500);
+    let mut attempt = 0;
+    loop {
+        let error = match session.get_cancellable(path, cancel_flag).await {
+            Err(e) if !e.contains("cancelled") && attempt < retries => e,
+            result => return result,
+        };
+        attempt += 1;
+        spend_retry(operation_id, session.retry_budget).await?;
+        let wait = match error.strip_prefix(RATE_LIMITED_ERROR) {
+            Some(secs) => std::time::Duration::from_secs(secs.trim().parse::<u64>().unwrap_or(5).clamp(1, 60)),
+            None => delay,
+        };
+        eprintln!("[FETCH {}] Count failed ({}), retrying in {:?}", operation_id, error, wait);
+        sleep_cancellable(wait, cancel_flag, session.cancel_check_interval).await?;
+        delay *= 2;
+    }
+}
+
+/// The pagination loop behind every collection fetch. Each page is handed to
+/// `on_page` together with the path of the page after it (`None` on the last
+/// page), so callers decide whether rows are kept in memory or written out.
+/// Returns the number of rows fetched and of pages that needed a reduced `$top`.
+async fn fetch_pages<F>(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    request: &FetchRequest,
+    cancel_flag: &AtomicBool,
+    resume_from: Option<ResumePoint>,
+    mut on_page: F,
+) -> Result<FetchedPages, String>
+where
+    F: FnMut(Vec<Transaction>, Option<&str>) -> Result<(), String>,
+{
+    let division = request.division;
+    let endpoint = request.endpoint.as_str();
+    let path = collection_path(request)?;
+    let expand = request.expand.clone().unwrap_or_default();
+    // A restart may have cancelled us before we got going
+    check_cancelled(cancel_flag)?;
+    check_division(division, cancel_flag).await?;
+
+    // Every log line of a fetch is tagged with its operation id, the same id the
+    // progress and completion events carry, so one fetch can be followed end to end
+    match &resume_from {
+        Some(resume) => eprintln!(
+            "[FETCH {}] Resuming {} for division {} after {} rows",
+            operation_id, endpoint, division, resume.rows
+        ),
+        None => eprintln!("[FETCH {}] Fetching {} for division {}", operation_id, endpoint, division),
+    }
+
+    let (session, progress_interval, date_mode, configured_page_size, count_retries) = {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        let refresh_at = state.refresh_at;
+        state.refresh_token().await?;
+        if state.refresh_at != refresh_at {
+            eprintln!("[FETCH {}] Access token refreshed", operation_id);
+        }
+        let progress_interval =
+            std::time::Duration::from_millis(state.config.progress_interval_ms.unwrap_or(100));
+        let date_mode = DateMode::new(request.raw_dates, state.config.scan_all_dates.unwrap_or(false));
+        (
+            state.session()?,
+            progress_interval,
+            date_mode,
+            state.config.page_size(endpoint),
+            state.config.count_retries.unwrap_or(2),
+        )
+    };
+
+    let mut fetched = resume_from.as_ref().map(|r| r.rows).unwrap_or(0);
+    let mut next_path = Some(resume_from.map(|r| r.next_path).unwrap_or(path));
+
+    // First, try to get an estimate of total count
+    let count_path = count_path(request);
+    let mut estimated_total: Option<i32> = None;
+    let count = fetch_count(&session, operation_id, &count_path, count_retries, cancel_flag).await;
+    // Check for cancellation before continuing
+    check_cancelled(cancel_flag)?;
+    let count_value = match count {
+        Ok(response) => parse_count(&response).ok_or_else(|| "Unrecognized count response".to_string()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &count_value {
+        eprintln!("[FETCH {}] Count unavailable, progress has no total: {}", operation_id, e);
+        if !request.silent {
+            let _ = app.emit("count-unavailable", serde_json::json!({
+                "operation_id": operation_id,
+                "division": division,
+                "message": e
+            }));
+        }
+    }
+    if let Ok(count_value) = count_value {
+        eprintln!("[FETCH {}] Count: {}", operation_id, count_value);
+        let count_value = request.top.map_or(count_value, |top| count_value.min(top.into()));
+        estimated_total = Some(count_value as i32);
+        if !request.silent {
+            let _ = app.emit("transaction-progress", serde_json::json!({
+                "operation_id": operation_id,
+                "division": division,
+                "current": 0,
+                "total": count_value,
+                "message": format!("Found {} transactions, starting fetch...", count_value)
+            }));
+        }
+    }
+
+    // Nothing matches the filter, so there is no point in requesting any pages
+    if estimated_total == Some(0) {
+        eprintln!("[FETCH {}] Complete: no matching rows", operation_id);
+        on_page(Vec::new(), None)?;
+        if !request.silent {
+            emit_transactions_complete(app, operation_id, 0, 0).await;
+        }
+        return Ok(FetchedPages { rows: 0, reduced_pages: 0 });
+    }
+
+    let mut last_progress: Option<std::time::Instant> = None;
+    // An explicit $top already limits the pages and must survive in the next links
+    let page_size = match request.top {
+        Some(_) => None,
+        None => Some(configured_page_size),
+    };
+    let mut reduced_pages = 0;
+    let mut pages = 0;
+
+    // Failures are also reported as an event with how far the fetch got, so the UI
+    // can say where it stopped and offer a retry or resume
+    let result: Result<(), String> = async {
+        while let Some(path) = next_path {
+            pages += 1;
+            wait_while_paused(operation_id, cancel_flag, session.cancel_check_interval).await?;
+            check_cancelled(cancel_flag)?;
+
+            let mut rows = Vec::new();
+            let (next, reduced_top) = get_page(&session, operation_id, &path, page_size, cancel_flag, |map| {
+                if rows.len() % session.cancel_check_rows == 0 {
+                    check_cancelled(cancel_flag)?;
+                }
+                rows.push(Transaction {
+                    data: normalize_row(map, &expand, date_mode),
+                    source_division: Some(division),
+                });
+                Ok(())
+            })
+            .await?;
+
+            next_path = next.map(|next| {
+                next.strip_prefix(&session.api)
+                    .unwrap_or(&next)
+                    .to_string()
+            });
+            if let Some(top) = reduced_top {
+                // $top ends the result set, so a full reduced page without a next link
+                // means the rows after it can't be reached
+                if next_path.is_none() && rows.len() >= top as usize {
+                    return Err(format!(
+                        "Page only succeeded with $top={} and Exact returned no link to the rows after it",
+                        top
+                    ));
+                }
+                // Back to the normal page size from the next page on
+                next_path = next_path.map(|next| set_top(&next, None));
+                reduced_pages += 1;
+            }
+            fetched += rows.len();
+            eprintln!(
+                "[FETCH {}] Page with {} rows ({} so far, more: {})",
+                operation_id,
+                rows.len(),
+                fetched,
+                next_path.is_some()
+            );
+            on_page(rows, next_path.as_deref())?;
+
+            // Emit progress update, throttled so small pages don't flood the UI. The last
+            // page is always reported.
+            let is_last_page = next_path.is_none();
+            let throttled = last_progress.is_some_and(|t| t.elapsed() < progress_interval);
+            if !request.silent && (!throttled || is_last_page) {
+                last_progress = Some(std::time::Instant::now());
+                emit_page_progress(app, operation_id, division, fetched, estimated_total);
+            }
+
+            // Check for cancellation after processing batch
+            check_cancelled(cancel_flag)?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = &result {
+        if !request.silent {
+            let _ = app.emit("transaction-error", serde_json::json!({
+                "operation_id": operation_id,
+                "division": division,
+                "kind": error_kind(e),
+                "message": e,
+                "page": pages,
+                "rows_fetched": fetched
+            }));
+        }
+    }
+    result?;
+
+    eprintln!("[FETCH {}] Complete: {} rows", operation_id, fetched);
+    if !request.silent {
+        emit_transactions_complete(app, operation_id, fetched, reduced_pages).await;
+    }
+
+    Ok(FetchedPages { rows: fetched, reduced_pages })
+}
+
+/// What `fetch_pages` got through: rows fetched and pages retried at a reduced size
+#[derive(Debug, Clone, Copy)]
+struct FetchedPages {
+    rows: usize,
+    reduced_pages: usize,
+}
+
+/// Divisors of the page size tried, in order, when a page fails with a 5xx
+const PAGE_SIZE_FALLBACK_DIVISORS: &[u32] = &[4, 20];
+
+/// Fetches one page. Exact's bulk endpoints sometimes answer a large page with a
+/// 5xx that succeeds at a smaller size, so without an explicit `$top` (`page_size`
+/// is `None`) on a server error the page is retried with a reduced `$top`.
+/// Returns the page's `__next` link and the `$top` that worked when it had to be
+/// reduced. A 5xx arrives before any row is read, so retries don't repeat rows.
+async fn get_page<F>(
+    session: &ApiSession,
+    operation_id: &str,
+    path: &str,
+    page_size: Option<u32>,
+    cancel_flag: &AtomicBool,
+    mut on_row: F,
+) -> Result<(Option<String>, Option<u32>), String>
+where
+    F: FnMut(serde_json::Map<String, serde_json::Value>) -> Result<(), String> + Send,
+{
+    let is_server_error = |e: &str| e.starts_with("API error (5");
+    let error = match session.get_rows(path, cancel_flag, &mut on_row).await {
+        Err(e) if is_server_error(&e) && page_size.is_some() => e,
+        result => return result.map(|next| (next, None)),
+    };
+    let page_size = page_size.unwrap_or_default();
+    for divisor in PAGE_SIZE_FALLBACK_DIVISORS {
+        let top = (page_size / divisor).max(1);
+        spend_retry(operation_id, session.retry_budget).await?;
+        eprintln!(
+            "[FETCH {}] Page failed, retrying with $top={}: {}",
+            operation_id, top, error
+        );
+        match session.get_rows(&set_top(path, Some(top)), cancel_flag, &mut on_row).await {
+            Ok(next) => return Ok((next, Some(top))),
+            Err(e) if is_server_error(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(error)
+}
+
+/// Bodies above this size (or of unknown size) are parsed while they download
+const STREAMING_THRESHOLD_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Blocking `Read` over the body chunks the download task sends
+struct ChunkReader {
+    chunks: tokio::sync::mpsc::Receiver<Vec<u8>>,
+    current: Vec<u8>,
+    offset: usize,
+}
+
+impl std::io::Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.offset >= self.current.len() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => {
+                    self.current = chunk;
+                    self.offset = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len() - self.offset);
+        buf[..n].copy_from_slice(&self.current[self.offset..self.offset + n]);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+type RowSender = tokio::sync::mpsc::Sender<serde_json::Map<String, serde_json::Value>>;
+
+/// Parses an OData page from `reader`, sending each row as soon as it is complete.
+/// Accepts the same envelopes as `extract_page`. Returns the `__next` link.
+fn parse_page_stream(reader: ChunkReader, rows: RowSender) -> Result<Option<String>, String> {
+    use serde::de::DeserializeSeed;
+    let mut deserializer = serde_json::Deserializer::from_reader(std::io::BufReader::new(reader));
+    let page = PageSeed { rows: &rows }
+        .deserialize(&mut deserializer)
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    deserializer
+        .end()
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    if let Some(error) = page.error {
+        return Err(format!("API error: {}", error));
+    }
+    Ok(page.next)
+}
+
+#[derive(Default)]
+struct StreamedPage {
+    next: Option<String>,
+    error: Option<serde_json::Value>,
+}
+
+/// The whole response: `{ "d": ... }`, `{ "error": ... }` or a bare array of rows
+struct PageSeed<'a> {
+    rows: &'a RowSender,
+}
+
+/// The value of `d`: `{ "results": [...], "__next": ... }`, an array or one record
+struct EnvelopeSeed<'a> {
+    rows: &'a RowSender,
+}
+
+/// An array of rows, each sent on as it is parsed
+struct RowsSeed<'a> {
+    rows: &'a RowSender,
+}
+
+fn send_row<E: serde::de::Error>(rows: &RowSender, row: serde_json::Map<String, serde_json::Value>) -> Result<(), E> {
+    rows.blocking_send(row)
+        .map_err(|_| E::custom("the page is no longer being read"))
+}
+
+impl<'de> serde::de::DeserializeSeed<'de> for PageSeed<'_> {
+    type Value = StreamedPage;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<StreamedPage, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for PageSeed<'_> {
+    type Value = StreamedPage;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("an OData envelope")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<StreamedPage, A::Error> {
+        RowsSeed { rows: self.rows }.visit_seq(seq)?;
+        Ok(StreamedPage::default())
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<StreamedPage, A::Error> {
+        let mut page = StreamedPage::default();
+        let mut has_envelope = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "d" => {
+                    page.next = map.next_value_seed(EnvelopeSeed { rows: self.rows })?;
+                    has_envelope = true;
+                }
+                "error" => page.error = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+        if !has_envelope && page.error.is_none() {
+            return Err(serde::de::Error::custom(
+                "Unrecognized response: expected an OData envelope, got object without a \"d\" envelope",
+            ));
+        }
+        Ok(page)
+    }
+}
+
+impl<'de> serde::de::DeserializeSeed<'de> for EnvelopeSeed<'_> {
+    type Value = Option<String>;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Option<String>, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for EnvelopeSeed<'_> {
+    type Value = Option<String>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a record or a list")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<Option<String>, A::Error> {
+        RowsSeed { rows: self.rows }.visit_seq(seq)?;
+        Ok(None)
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Option<String>, A::Error> {
+        let mut next = None;
+        let mut has_results = false;
+        let mut record = serde_json::Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "results" => {
+                    map.next_value_seed(RowsSeed { rows: self.rows })?;
+                    has_results = true;
+                }
+                "__next" => next = map.next_value::<Option<String>>()?,
+                _ => {
+                    let value = map.next_value()?;
+                    record.insert(key, value);
+                }
+            }
+        }
+        if !has_results {
+            // `d` held a single record rather than a list
+            send_row(self.rows, record)?;
+        }
+        Ok(next)
+    }
+}
+
+impl<'de> serde::de::DeserializeSeed<'de> for RowsSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for RowsSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a list of records")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(row) = seq.next_element::<serde_json::Value>()? {
+            if let serde_json::Value::Object(row) = row {
+                send_row(self.rows, row)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replaces (or with `None` removes) the `$top` query option of a page path
+fn set_top(path: &str, top: Option<u32>) -> String {
+    let (base, query) = path.split_once('?').unwrap_or((path, ""));
+    let mut options: Vec<String> = query
+        .split('&')
+        .filter(|option| !option.is_empty() && !option.starts_with("$top="))
+        .map(String::from)
+        .collect();
+    if let Some(top) = top {
+        options.push(format!("$top={}", top));
+    }
+    if options.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}?{}", base, options.join("&"))
+    }
+}
+
+/// Unwraps one page of an OData response. Besides the usual `{ "d": { "results": [...] } }`
+/// this accepts `{ "d": [...] }`, a single `{ "d": { ... } }` record and a bare array.
+fn extract_page(json: serde_json::Value) -> Result<ApiData<serde_json::Value>, String> {
+    let shape = match &json {
+        serde_json::Value::Object(map) if map.contains_key("d") => "object",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object without a \"d\" envelope",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Null => "null",
+    };
+    match json {
+        serde_json::Value::Array(results) => Ok(ApiData { results, __next: None }),
+        serde_json::Value::Object(mut map) if map.contains_key("d") => match map.remove("d") {
+            Some(serde_json::Value::Array(results)) => Ok(ApiData { results, __next: None }),
+            Some(serde_json::Value::Object(d)) if d.contains_key("results") => {
+                serde_json::from_value(serde_json::Value::Object(d))
+                    .map_err(|e| format!("Failed to parse results: {}", e))
+            }
+            Some(record @ serde_json::Value::Object(_)) => Ok(ApiData {
+                results: vec![record],
+                __next: None,
+            }),
+            _ => Err("Unrecognized response: \"d\" is neither a record nor a list".to_string()),
+        },
+        _ => Err(format!("Unrecognized response: expected an OData envelope, got {}", shape)),
+    }
+}
+
+fn find_current_division(value: &serde_json::Value) -> Option<i32> {
+    match value {
+        serde_json::Value::Object(map) => map
+            .get("CurrentDivision")
+            .and_then(parse_count)
+            .map(|division| division as i32)
+            .or_else(|| map.values().find_map(find_current_division)),
+        serde_json::Value::Array(items) => items.iter().find_map(find_current_division),
+        _ => None,
+    }
+}
+
+/// Extracts a `$count` result, which Exact returns as a bare number but may also
+/// wrap as `{ "d": N }`, `{ "d": { "__count": N } }` or encode as a string
+fn parse_count(value: &serde_json::Value) -> Option<i64> {
+    match value {
+        serde_json::Value::Number(n) => n.as_i64(),
+        serde_json::Value::String(s) => s.trim().parse::<i64>().ok(),
+        serde_json::Value::Object(map) => map
+            .get("d")
+            .or_else(|| map.get("__count"))
+            .and_then(parse_count),
+        _ => None,
+    }
+}
+
+fn date_regex() -> &'static regex::Regex {
+    static DATE_REGEX: OnceLock<regex::Regex> = OnceLock::new();
+    DATE_REGEX.get_or_init(|| regex::Regex::new(r"/Date\((\d+)\)/").unwrap())
+}
+
+/// Converts Exact's `/Date(ms)/` strings to RFC3339, leaving anything else untouched
+fn normalize_date(value: serde_json::Value) -> serde_json::Value {
+    if let serde_json::Value::String(s) = &value {
+        if let Some(captures) = date_regex().captures(s) {
+            if let Ok(timestamp_ms) = captures[1].parse::<i64>() {
+                if let Some(dt) = chrono::DateTime::<chrono::Utc>::from_timestamp(timestamp_ms / 1000, 0) {
+                    return serde_json::Value::String(dt.to_rfc3339());
+                }
+            }
+        }
+    }
+    value
+}
+
+/// Parses OData's string-encoded decimals in amount fields into numbers
+fn normalize_amount(value: serde_json::Value) -> serde_json::Value {
+    if let serde_json::Value::String(s) = &value {
+        if let Some(number) = s.trim().parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+            return serde_json::Value::Number(number);
+        }
+    }
+    value
+}
+
+/// Which string fields are converted from `/Date(ms)/` to RFC3339
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateMode {
+    /// Keep Exact's native strings
+    Raw,
+    /// Only the fields in `DATE_FIELDS`, so genuine text is never rewritten
+    KnownFields,
+    /// Every string that looks like a date
+    AllStrings,
+}
+
+impl DateMode {
+    fn new(raw_dates: bool, scan_all_dates: bool) -> Self {
+        if raw_dates {
+            DateMode::Raw
+        } else if scan_all_dates {
+            DateMode::AllStrings
+        } else {
+            DateMode::KnownFields
+        }
+    }
+}
+
+/// Normalizes the dates of one result row according to `date_mode` and the
+/// amount fields, and drops nested objects except for expanded navigation
+/// properties listed in `keep_objects`
+fn normalize_row(
+    map: serde_json::Map<String, serde_json::Value>,
+    keep_objects: &[String],
+    date_mode: DateMode,
+) -> HashMap<String, serde_json::Value> {
+    let mut data = HashMap::new();
+    for (key, value) in map {
+        if value.is_object() && !keep_objects.iter().any(|p| p.split('/').next() == Some(key.as_str())) {
+            continue;
+        }
+        let value = match (field_kind(&key), date_mode) {
+            (FieldKind::Amount, _) => normalize_amount(value),
+            (_, DateMode::Raw) => value,
+            (FieldKind::Date, _) | (_, DateMode::AllStrings) => normalize_date(value),
+            _ => value,
+        };
+        data.insert(key, value);
+    }
+    data
+}
+
+/// Sort key that orders names case-insensitively and ignores diacritics, so
+/// "Ëlla" sorts next to "ella" instead of after "zeeman". With the `binary`
+/// locale names are compared byte-wise as before.
+fn collation_key(name: &str, locale: &str) -> String {
+    if locale == "binary" {
+        return name.to_string();
+    }
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+            'ç' | 'ć' | 'č' => 'c',
+            'ď' | 'đ' => 'd',
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => 'e',
+            'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' => 'i',
+            'ñ' | 'ń' | 'ň' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => 'o',
+            'ŕ' | 'ř' => 'r',
+            'ś' | 'š' | 'ş' => 's',
+            'ť' | 'ţ' => 't',
+            'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => 'u',
+            'ý' | 'ÿ' => 'y',
+            'ź' | 'ż' | 'ž' => 'z',
+            other => other,
+        })
+        .collect()
+}
+
+/// Endpoints are interpolated into the URL, so only allow plain path segments
+fn validate_endpoint(endpoint: &str) -> Result<(), String> {
+    let valid = !endpoint.is_empty()
+        && !endpoint.starts_with('/')
+        && endpoint.split('/').all(|segment| {
+            !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    if !valid {
+        return Err(format!("Invalid endpoint: {}", endpoint));
+    }
+    Ok(())
+}
+
+fn validate_select(endpoint: &str, select: &[String]) -> Result<(), String> {
+    let Some(allowed) = selectable_fields(endpoint) else {
+        return Ok(());
+    };
+    let invalid: Vec<&str> = select
+        .iter()
+        .map(|f| f.as_str())
+        .filter(|f| !allowed.contains(f))
+        .collect();
+    if !invalid.is_empty() {
+        return Err(format!(
+            "Cannot select {} on {}",
+            invalid.join(", "),
+            endpoint
+        ));
+    }
+    Ok(())
+}
+
+fn validate_expand(endpoint: &str, expand: &[String]) -> Result<(), String> {
+    if expand.is_empty() {
+        return Ok(());
+    }
+    if endpoint.starts_with("bulk/") || endpoint.starts_with("sync/") {
+        return Err(format!("Endpoint {} does not support $expand", endpoint));
+    }
+    for property in expand {
+        let well_formed = property.split('/').all(|segment| {
+            !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+        if !well_formed {
+            return Err(format!("Invalid $expand path: {}", property));
+        }
+    }
+    if let Some((_, allowed)) = EXPANDABLE_ENDPOINTS.iter().find(|(e, _)| *e == endpoint) {
+        let invalid: Vec<&str> = expand
+            .iter()
+            .map(|p| p.as_str())
+            .filter(|p| !allowed.contains(&p.split('/').next().unwrap_or(p)))
+            .collect();
+        if !invalid.is_empty() {
+            return Err(format!(
+                "Cannot expand {} on {} (supported: {})",
+                invalid.join(", "),
+                endpoint,
+                allowed.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the in-memory transaction cache with a fresh result; a failed fetch
+/// clears it so stale rows are never served. A successful fetch is also written to
+/// the offline cache of each division it has rows for (rows without a
+/// `source_division` belong to `division`); a failed one leaves that cache alone.
+async fn remember_transactions(division: Option<i32>, result: &Result<Vec<Transaction>, String>) {
+    let Ok(mut state_guard) = get_app_state().await else {
+        return;
+    };
+    let Some(state) = state_guard.as_mut() else {
+        return;
+    };
+    state.cached_transactions = result.as_ref().cloned().unwrap_or_default();
+    state.cached_at = result.is_ok().then(chrono::Utc::now);
+    let Ok(rows) = result else {
+        return;
+    };
+    let dir = state.data_dir.join(OFFLINE_DIR);
+    drop(state_guard);
+
+    let mut by_division: BTreeMap<i32, Vec<&Transaction>> = BTreeMap::new();
+    if let Some(division) = division {
+        by_division.entry(division).or_default();
+    }
+    for row in rows {
+        if let Some(division) = row.source_division.or(division) {
+            by_division.entry(division).or_default().push(row);
+        }
+    }
+    let fetched_at = chrono::Utc::now();
+    for (division, rows) in by_division {
+        if let Err(e) = save_offline(&dir, division, fetched_at, &rows) {
+            eprintln!("[OFFLINE] Failed to cache division {}: {}", division, e);
+        }
+    }
+}
+
+/// Folder under the data dir holding `<division>.ndjson` per division: a
+/// `{"fetched_at": ...}` line followed by one `Transaction` per line
+const OFFLINE_DIR: &str = "offline";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OfflineHeader {
+    fetched_at: chrono::DateTime<chrono::Utc>,
+}
+
+fn save_offline(
+    dir: &Path,
+    division: i32,
+    fetched_at: chrono::DateTime<chrono::Utc>,
+    rows: &[&Transaction],
+) -> Result<(), String> {
+    use std::io::Write;
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let file = dir.join(format!("{}.ndjson", division));
+    let partial_file = file.with_extension("ndjson.partial");
+    let write_error = |e: std::io::Error| format!("Failed to write {}: {}", partial_file.display(), e);
+    let mut writer = std::io::BufWriter::new(fs::File::create(&partial_file).map_err(write_error)?);
+    let header = OfflineHeader { fetched_at };
+    for line in std::iter::once(serde_json::to_value(&header)).chain(rows.iter().map(serde_json::to_value)) {
+        let line = line.map_err(|e| format!("Failed to serialize offline rows: {}", e))?;
+        serde_json::to_writer(&mut writer, &line)
+            .map_err(|e| format!("Failed to write {}: {}", partial_file.display(), e))?;
+        writer.write_all(b"\n").map_err(write_error)?;
+    }
+    writer.flush().map_err(write_error)?;
+    drop(writer);
+    fs::rename(&partial_file, &file).map_err(|e| format!("Failed to move {} into place: {}", partial_file.display(), e))
+}
+
+/// The offline cache of a division, `None` when nothing was ever cached for it
+fn load_offline(dir: &Path, division: i32) -> Result<Option<(OfflineHeader, Vec<Transaction>)>, String> {
+    let file = dir.join(format!("{}.ndjson", division));
+    let content = match fs::read_to_string(&file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", file.display(), e)),
+    };
+    let parse_error = |e: serde_json::Error| format!("Failed to parse {}: {}", file.display(), e);
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+    let Some(header) = lines.next() else {
+        return Ok(None);
+    };
+    let header: OfflineHeader = serde_json::from_str(header).map_err(parse_error)?;
+    let rows = lines
+        .map(|line| serde_json::from_str(line).map_err(parse_error))
+        .collect::<Result<Vec<Transaction>, _>>()?;
+    Ok(Some((header, rows)))
+}
+
+/// Compares two JSON values numerically when both are numbers, otherwise as strings
+/// (which also orders RFC3339 dates correctly)
+fn compare_values(a: &serde_json::Value, b: &serde_json::Value) -> Option<std::cmp::Ordering> {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(x), Some(y)) => x.partial_cmp(&y),
+        _ => match (a.as_str(), b.as_str()) {
+            (Some(x), Some(y)) => Some(x.cmp(y)),
+            _ => None,
+        },
+    }
+}
+
+/// Shared CSV writer so every export quotes and escapes the same way
+fn write_csv<I>(path: &str, headers: &[&str], rows: I) -> Result<(), String>
+where
+    I: IntoIterator<Item = Vec<String>>,
+{
+    let mut writer = csv::Writer::from_path(path).map_err(|e| format!("Failed to create CSV file: {}", e))?;
+    writer
+        .write_record(headers)
+        .map_err(|e| format!("Failed to write CSV: {}", e))?;
+    for row in rows {
+        writer
+            .write_record(&row)
+            .map_err(|e| format!("Failed to write CSV: {}", e))?;
+    }
+    writer.flush().map_err(|e| format!("Failed to write CSV: {}", e))?;
+    Ok(())
+}
+
+fn emit_page_progress(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    division: i32,
+    current: usize,
+    estimated_total: Option<i32>,
+) {
+    let current_count = current as i64;
+    let message = if let Some(total) = estimated_total {
+        format!("Fetched {} of {} transactions...", current_count, total)
+    } else {
+        format!("Fetched {} transactions so far...", current_count)
+    };
+    let total = estimated_total.map(|t| t as i64).unwrap_or(-1); // Use -1 to indicate unknown
+    let _ = app.emit("transaction-progress", serde_json::json!({
+        "operation_id": operation_id,
+        "division": division,
+        "current": current_count,
+        "total": total,
+        "message": message
+    }));
+}
+
+/// Signals a successful fetch so the UI can tell "no matches" apart from a failure
+/// `reduced_pages` counts pages that only succeeded after retrying at a smaller size
+async fn emit_transactions_complete(app: &tauri::AppHandle, operation_id: &str, matched: usize, reduced_pages: usize) {
+    let retries = retries_used(operation_id).await;
+    let _ = app.emit(
+        "transaction-complete",
+        completion_payload(operation_id, matched, reduced_pages, retries),
+    );
+}
+
+/// Payload of the `transaction-complete` event; a fetch that matched nothing
+/// still completes successfully with `matched: 0`
+fn completion_payload(operation_id: &str, matched: usize, reduced_pages: usize, retries: u32) -> serde_json::Value {
+    serde_json::json!({
+        "operation_id": operation_id,
+        "success": true,
+        "matched": matched,
+        "reduced_pages": reduced_pages,
+        "retries": retries
+    })
+}
+
+#[tauri::command]
+async fn is_authenticated() -> bool {
+    if let Ok(state) = get_app_state().await {
+        if let Some(s) = state.as_ref() {
+            return s.access_token.is_some();
+        }
+    }
+    false
+}
+
+/// Unlocks the passphrase protected token store for this session and loads the
+/// stored tokens; returns whether they make us authenticated. The first unlock,
+/// before any tokens are stored, sets the passphrase.
+#[tauri::command]
+async fn unlock(passphrase: String) -> Result<bool, String> {
+    let result = async {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        state.token_store.unlock(&passphrase)?;
+        state.load_tokens();
+        Ok::<_, String>(state.access_token.is_some())
+    }
+    .await;
+    record_errors("unlock", result).await
+}
+
+/// Cancels the operation with the given id or label, or every running operation
+/// without a target
+#[tauri::command]
+async fn cancel_operation(target: Option<String>) -> Result<(), String> {
+    let Some(target) = target else {
+        let operations = OPERATIONS.lock().await;
+        for operation in operations.values() {
+            operation.cancel_flag.store(true, Ordering::Relaxed);
+        }
+        return Ok(());
+    };
+    let result = async {
+        let operation_id = resolve_operation(&target).await?;
+        let operations = OPERATIONS.lock().await;
+        let operation = operations
+            .get(&operation_id)
+            .ok_or(format!("No running operation {}", target))?;
+        operation.cancel_flag.store(true, Ordering::Relaxed);
+        Ok::<_, String>(())
+    }
+    .await;
+    record_errors("cancel_operation", result).await
+}
+
+/// Stops a fetch after the page in flight, keeping what it has fetched so far,
+/// until `resume_operation` or a cancel
+#[tauri::command]
+async fn pause_operation(app: tauri::AppHandle, id: String) -> Result<(), String> {
+    record_errors("pause_operation", set_paused(&id, true).await).await?;
+    let _ = app.emit("operation-paused", serde_json::json!({ "operation_id": id }));
+    Ok(())
+}
+
+#[tauri::command]
+async fn resume_operation(app: tauri::AppHandle, id: String) -> Result<(), String> {
+    record_errors("resume_operation", set_paused(&id, false).await).await?;
+    let _ = app.emit("operation-resumed", serde_json::json!({ "operation_id": id }));
+    Ok(())
+}
+
+#[tauri::command]
+async fn logout() -> Result<(), String> {
+    record_errors("logout", clear_session().await).await
+}
+
+async fn clear_session() -> Result<(), String> {
+    let mut state_guard = get_app_state().await?;
+    let state = state_guard.as_mut().ok_or("State not initialized")?;
+    
+    // Clear tokens from memory
+    state.access_token = None;
+    state.refresh_token = None;
+    state.refresh_at = 0;
+    state.current_division = None;
+    state.cached_transactions.clear();
+    state.cached_at = None;
+    state.vat_codes.clear();
+    state.code_lists.clear();
+    state.journals.clear();
+    state.projects.clear();
+    state.gl_classifications.clear();
+    state.payment_conditions.clear();
+    state.custom_fields.clear();
+    state.users.clear();
+    state.division_names.clear();
+    state.division_access.clear();
+    state.validators.clear();
+    state.last_refresh = None;
+    state.pending_login = None;
+
+    // Offline copies of the fetched data go with the session
+    let offline_dir = state.data_dir.join(OFFLINE_DIR);
+    if let Err(e) = fs::remove_dir_all(&offline_dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("[OFFLINE] Failed to remove {}: {}", offline_dir.display(), e);
+        }
+    }
+    
+    // Delete stored tokens
+    state.token_store.clear()
+
+}
+
+#[tauri::command]
+async fn save_query(
+    name: String,
+    division: i32,
+    endpoint: Option<String>,
+    filter: Option<String>,
+    select: Option<Vec<String>>,
+) -> Result<(), String> {
+    let query = SavedQuery {
+        name: name.trim().to_string(),
+        division,
+        endpoint: endpoint.unwrap_or_else(|| TRANSACTION_LINES_ENDPOINT.to_string()),
+        filter,
+        select,
+    };
+    record_errors("save_query", store_saved_query(query).await).await
+}
+
+async fn store_saved_query(query: SavedQuery) -> Result<(), String> {
+    if query.name.is_empty() {
+        return Err("Query name cannot be empty".to_string());
+    }
+    validate_endpoint(&query.endpoint)?;
+
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    let mut queries = state.load_saved_queries()?;
+    queries.retain(|q| q.name != query.name);
+    queries.push(query);
+    queries.sort_by(|a, b| a.name.cmp(&b.name));
+    state.save_saved_queries(&queries)
+}
+
+#[tauri::command]
+async fn list_saved_queries() -> Result<Vec<SavedQuery>, String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    state.load_saved_queries()
+}
+
+#[tauri::command]
+async fn delete_saved_query(name: String) -> Result<(), String> {
+    record_errors("delete_saved_query", remove_saved_query(&name).await).await
+}
+
+async fn remove_saved_query(name: &str) -> Result<(), String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    let mut queries = state.load_saved_queries()?;
+    let before = queries.len();
+    queries.retain(|q| q.name != name);
+    if queries.len() == before {
+        return Err(format!("No saved query named {}", name));
+    }
+    state.save_saved_queries(&queries)
+}
+
+#[tauri::command]
+async fn run_saved_query(app: tauri::AppHandle, name: String) -> Result<Vec<Transaction>, String> {
+    let query = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        state
+            .load_saved_queries()?
+            .into_iter()
+            .find(|q| q.name == name)
+            .ok_or(format!("No saved query named {}", name))?
+    };
+
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let request = FetchRequest {
+        division: query.division,
+        endpoint: query.endpoint,
+        select: query.select,
+        filter: query.filter,
+        ..Default::default()
+    };
+    let result = fetch_collection(&app, &operation_id, &request, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("run_saved_query", result).await
+}
+
+/// A simple filter on one field: equality and/or an inclusive range
+#[derive(Debug, Clone, Deserialize)]
+struct FieldPredicate {
+    field: String,
+    eq: Option<serde_json::Value>,
+    min: Option<serde_json::Value>,
+    max: Option<serde_json::Value>,
+}
+
+impl FieldPredicate {
+    fn matches(&self, transaction: &Transaction) -> bool {
+        let Some(value) = transaction.data.get(&self.field) else {
+            return false;
+        };
+        if let Some(eq) = &self.eq {
+            if compare_values(value, eq) != Some(std::cmp::Ordering::Equal) && value != eq {
+                return false;
+            }
+        }
+        if let Some(min) = &self.min {
+            if !matches!(compare_values(value, min), Some(std::cmp::Ordering::Greater | std::cmp::Ordering::Equal)) {
+                return false;
+            }
+        }
+        if let Some(max) = &self.max {
+            if !matches!(compare_values(value, max), Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Filters the last fetched transactions in memory; all predicates must match
+#[tauri::command]
+async fn query_cached_transactions(predicates: Vec<FieldPredicate>) -> Result<Vec<Transaction>, String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    Ok(state
+        .cached_transactions
+        .iter()
+        .filter(|t| predicates.iter().all(|p| p.matches(t)))
+        .cloned()
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+struct OfflineTransactions {
+    transactions: Vec<Transaction>,
+    /// When the rows were fetched, `None` when nothing is cached for the division
+    fetched_at: Option<String>,
+    age_secs: Option<i64>,
+}
+
+/// The last fetched transactions of a division, read from the offline cache under
+/// the data dir and filtered in memory, for browsing while `connectivity_status`
+/// reports the API unreachable. Never touches the network. Only the division's
+/// latest fetch is kept, so after a filtered fetch just those rows are available;
+/// the cache survives restarts and division switches and goes on logout.
+#[tauri::command]
+async fn get_transactions_offline(
+    division: i32,
+    predicates: Option<Vec<FieldPredicate>>,
+) -> Result<OfflineTransactions, String> {
+    let result = async {
+        let dir = {
+            let state_guard = get_app_state().await?;
+            let state = state_guard.as_ref().ok_or("State not initialized")?;
+            state.data_dir.join(OFFLINE_DIR)
+        };
+        let predicates = predicates.unwrap_or_default();
+        let Some((OfflineHeader { fetched_at }, rows)) = load_offline(&dir, division)? else {
+            return Ok(OfflineTransactions { transactions: Vec::new(), fetched_at: None, age_secs: None });
+        };
+        Ok(OfflineTransactions {
+            transactions: rows.into_iter().filter(|t| predicates.iter().all(|p| p.matches(t))).collect(),
+            fetched_at: Some(fetched_at.to_rfc3339()),
+            age_secs: Some((chrono::Utc::now() - fetched_at).num_seconds()),
+        })
+    }
+    .await;
+    record_errors("get_transactions_offline", result).await
+}
+
+/// Folders under the data dir that `list_cache_entries` covers
+const CACHE_DIRS: &[&str] = &["metadata", "resumable", OFFLINE_DIR];
+
+/// A file in one of the data dir's caches
+#[derive(Debug, Clone, Serialize)]
+struct CacheEntry {
+    /// The cache folder: `metadata`, `resumable` or `offline`
+    cache: String,
+    /// File name within the folder, as passed to `clear_cache_entry`
+    name: String,
+    division: Option<i32>,
+    /// Endpoint, or service for metadata; `None` when the file name doesn't say
+    endpoint: Option<String>,
+    /// For a resumable fetch this includes its partial file
+    size_bytes: u64,
+    /// Last modified, RFC3339
+    fetched_at: Option<String>,
+}
+
+fn cache_entry(dir: &Path, cache: &str, name: &str) -> Option<CacheEntry> {
+    let metadata = fs::metadata(dir.join(name)).ok().filter(|m| m.is_file())?;
+    let mut size_bytes = metadata.len();
+    let (division, endpoint) = match cache {
+        "metadata" => match name.strip_suffix(".xml").and_then(|stem| stem.split_once('_')) {
+            Some((division, service)) => (division.parse().ok(), Some(service.to_string())),
+            None => (None, None),
+        },
+        "resumable" => match name.strip_suffix(".json").map(|id| load_resumable(dir, id)) {
+            Some(Ok(fetch)) => {
+                size_bytes += fs::metadata(fetch.partial_file()).map(|m| m.len()).unwrap_or(0);
+                (Some(fetch.request.division), Some(fetch.request.endpoint))
+            }
+            _ => (None, None),
+        },
+        _ => match name.strip_suffix(".ndjson").and_then(|stem| stem.parse().ok()) {
+            Some(division) => (Some(division), Some(TRANSACTION_LINES_ENDPOINT.to_string())),
+            None => (None, None),
+        },
+    };
+    Some(CacheEntry {
+        cache: cache.to_string(),
+        name: name.to_string(),
+        division,
+        endpoint,
+        size_bytes,
+        fetched_at: metadata
+            .modified()
+            .ok()
+            .map(|modified| chrono::DateTime::<chrono::Utc>::from(modified).to_rfc3339()),
+    })
+}
+
+fn cache_entries(data_dir: &Path) -> Vec<CacheEntry> {
+    let mut entries = Vec::new();
+    for cache in CACHE_DIRS {
+        let dir = data_dir.join(cache);
+        let Ok(files) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut names: Vec<String> = files
+            .flatten()
+            .filter_map(|file| file.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        entries.extend(names.iter().filter_map(|name| cache_entry(&dir, cache, name)));
+    }
+    entries
+}
+
+/// Deletes a cache file; a resumable fetch takes its partial file with it and is
+/// refused while it runs
+async fn remove_cache_entry(data_dir: &Path, entry: &CacheEntry) -> Result<(), String> {
+    let dir = data_dir.join(&entry.cache);
+    if entry.cache == "resumable" {
+        if let Some(id) = entry.name.strip_suffix(".json") {
+            if ACTIVE_RESUMABLE_FETCHES.lock().await.contains(id) {
+                return Err(format!("Resumable fetch {} is still running", id));
+            }
+            if let Ok(fetch) = load_resumable(&dir, id) {
+                let _ = fs::remove_file(fetch.partial_file());
+            }
+        }
+    }
+    let path = dir.join(&entry.name);
+    fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))
+}
+
+async fn cache_data_dir() -> Result<PathBuf, String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    Ok(state.data_dir.clone())
+}
+
+/// Every file in the metadata, resumable and offline caches with its size and age,
+/// so the user can see what is stored and clear it
+#[tauri::command]
+async fn list_cache_entries() -> Result<Vec<CacheEntry>, String> {
+    let result = cache_data_dir().await.map(|data_dir| cache_entries(&data_dir));
+    record_errors("list_cache_entries", result).await
+}
+
+/// Deletes one entry from `list_cache_entries`, named by its `cache` and `name`
+#[tauri::command]
+async fn clear_cache_entry(cache: String, name: String) -> Result<(), String> {
+    let result = async {
+        if !CACHE_DIRS.contains(&cache.as_str()) {
+            return Err(format!("Unknown cache: {}", cache));
+        }
+        let data_dir = cache_data_dir().await?;
+        // Only names the listing produced, so nothing outside the cache is reachable
+        let entry = cache_entries(&data_dir)
+            .into_iter()
+            .find(|entry| entry.cache == cache && entry.name == name)
+            .ok_or_else(|| format!("No cache entry {}/{}", cache, name))?;
+        remove_cache_entry(&data_dir, &entry).await
+    }
+    .await;
+    record_errors("clear_cache_entry", result).await
+}
+
+/// Deletes every cache entry except running resumable fetches. Returns how many
+/// entries were removed.
+#[tauri::command]
+async fn clear_all_cache() -> Result<usize, String> {
+    let result = async {
+        let data_dir = cache_data_dir().await?;
+        let mut removed = 0;
+        for entry in cache_entries(&data_dir) {
+            match remove_cache_entry(&data_dir, &entry).await {
+                Ok(()) => removed += 1,
+                Err(e) => eprintln!("[CACHE] Skipping {}/{}: {}", entry.cache, entry.name, e),
+            }
+        }
+        Ok::<_, String>(removed)
+    }
+    .await;
+    record_errors("clear_all_cache", result).await
+}
+
+/// One exported column: the field it holds, its header (the field name when
+/// unset) and its position. Columns are sorted by `order`, unordered ones last.
+#[derive(Debug, Clone, Deserialize)]
+struct ColumnSpec {
+    field: String,
+    header: Option<String>,
+    order: Option<i32>,
+}
+
+/// Resolves a column spec against the fields available in an export, returning
+/// the fields and their headers in column order. Fields not in the spec are left out.
+fn resolve_columns(spec: Vec<ColumnSpec>, available: &[&str]) -> Result<(Vec<String>, Vec<String>), String> {
+    if spec.is_empty() {
+        return Err("Column spec must list at least one field".to_string());
+    }
+    let mut seen = BTreeSet::new();
+    for column in &spec {
+        if !available.contains(&column.field.as_str()) {
+            return Err(format!("Column spec refers to unknown field {}", column.field));
+        }
+        if !seen.insert(column.field.as_str()) {
+            return Err(format!("Column spec lists {} more than once", column.field));
+        }
+    }
+    let mut spec = spec;
+    spec.sort_by_key(|column| (column.order.is_none(), column.order));
+    Ok(spec
+        .into_iter()
+        .map(|column| {
+            let header = column
+                .header
+                .filter(|header| !header.trim().is_empty())
+                .unwrap_or_else(|| column.field.clone());
+            (column.field, header)
+        })
+        .unzip())
+}
+
+/// Writes transactions to CSV: the known transaction fields in their usual order,
+/// then any other fields alphabetically. Empty cells mean the field was absent.
+/// A `column_spec` picks, renames and orders the columns instead.
+#[tauri::command]
+async fn export_transactions_csv(
+    transactions: Vec<Transaction>,
+    path: String,
+    column_spec: Option<Vec<ColumnSpec>>,
+) -> Result<usize, String> {
+    let mut fields = ordered_fields(&transactions);
+    if transactions.iter().any(|t| t.source_division.is_some()) {
+        fields.push(SOURCE_DIVISION_COLUMN);
+    }
+    let columns = match column_spec {
+        // Without rows there is nothing to check against but the usual fields
+        Some(spec) if transactions.is_empty() => {
+            resolve_columns(spec, &TRANSACTION_FIELDS.split(',').collect::<Vec<_>>())
+        }
+        Some(spec) => resolve_columns(spec, &fields),
+        None => Ok((
+            fields.iter().map(|f| f.to_string()).collect(),
+            fields.iter().map(|f| f.to_string()).collect(),
+        )),
+    };
+    let (fields, headers) = match columns {
+        Ok(columns) => columns,
+        Err(e) => return record_errors("export_transactions_csv", Err(e)).await,
+    };
+    let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+
+    let rows = transactions.iter().map(|t| {
+        fields
+            .iter()
+            .map(|field| {
+                if field == SOURCE_DIVISION_COLUMN {
+                    return t.source_division.map(|d| d.to_string()).unwrap_or_default();
+                }
+                csv_cell(t.data.get(field))
+            })
+            .collect()
+    });
+    let result = write_csv(&path, &headers, rows).map(|_| transactions.len());
+    record_errors("export_transactions_csv", result).await
+}
+
+const DEFAULT_SQLITE_TABLE: &str = "transactions";
+
+/// SQLite column type of a transaction field
+fn sqlite_type(field: &str) -> &'static str {
+    if field_kind(field) == FieldKind::Amount {
+        "REAL"
+    } else if INTEGER_FIELDS.contains(&field) || field == SOURCE_DIVISION_COLUMN {
+        "INTEGER"
+    } else {
+        "TEXT"
+    }
+}
+
+fn sqlite_value(value: Option<&serde_json::Value>) -> rusqlite::types::Value {
+    use rusqlite::types::Value;
+    match value {
+        None | Some(serde_json::Value::Null) => Value::Null,
+        Some(serde_json::Value::Bool(b)) => Value::Integer(*b as i64),
+        Some(serde_json::Value::Number(n)) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => n.as_f64().map(Value::Real).unwrap_or(Value::Null),
+        },
+        Some(serde_json::Value::String(s)) => Value::Text(s.clone()),
+        Some(other) => Value::Text(other.to_string()),
+    }
+}
+
+/// Writes transactions into a SQLite table (default `transactions`) with one
+/// column per known transaction field plus any extra fields in the rows, typed
+/// REAL for amounts, INTEGER for counters and TEXT otherwise. The table is
+/// replaced unless `append` is set; appending adds missing columns and replaces
+/// the rows of entries already present (same `EntryNumber` and `Document`), so
+/// a re-fetched entry doesn't end up twice. Returns the number of rows written.
+#[tauri::command]
+async fn export_transactions_sqlite(
+    transactions: Vec<Transaction>,
+    db_path: String,
+    table: Option<String>,
+    append: Option<bool>,
+) -> Result<usize, String> {
+    let table = table.unwrap_or_else(|| DEFAULT_SQLITE_TABLE.to_string());
+    let result = write_sqlite(&transactions, &db_path, &table, append.unwrap_or(false));
+    record_errors("export_transactions_sqlite", result).await
+}
+
+fn write_sqlite(transactions: &[Transaction], db_path: &str, table: &str, append: bool) -> Result<usize, String> {
+    let valid_name = table.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        return Err(format!("Invalid table name {}: use letters, digits and underscores", table));
+    }
+    let sql_error = |e: rusqlite::Error| format!("Failed to write {}: {}", db_path, e);
+
+    let mut columns: Vec<&str> = TRANSACTION_FIELDS.split(',').collect();
+    for field in ordered_fields(transactions) {
+        if !columns.contains(&field) {
+            columns.push(field);
+        }
+    }
+    columns.push(SOURCE_DIVISION_COLUMN);
+
+    let mut connection = rusqlite::Connection::open(db_path).map_err(sql_error)?;
+    let tx = connection.transaction().map_err(sql_error)?;
+    if !append {
+        tx.execute_batch(&format!("DROP TABLE IF EXISTS \"{}\"", table))
+            .map_err(sql_error)?;
+    }
+    let definitions: Vec<String> = columns
+        .iter()
+        .map(|column| format!("\"{}\" {}", column, sqlite_type(column)))
+        .collect();
+    tx.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS \"{}\" ({})",
+        table,
+        definitions.join(", ")
+    ))
+    .map_err(sql_error)?;
+
+    if append {
+        // An existing table may predate fields that are in these rows
+        let existing: BTreeSet<String> = {
+            let mut statement = tx
+                .prepare(&format!("PRAGMA table_info(\"{}\")", table))
+                .map_err(sql_error)?;
+            let names = statement
+                .query_map([], |row| row.get::<_, String>(1))
+                .map_err(sql_error)?
+                .collect::<Result<_, _>>()
+                .map_err(sql_error);
+            names?
+        };
+        for column in columns.iter().filter(|column| !existing.contains(**column)) {
+            tx.execute_batch(&format!(
+                "ALTER TABLE \"{}\" ADD COLUMN \"{}\" {}",
+                table,
+                column,
+                sqlite_type(column)
+            ))
+            .map_err(sql_error)?;
+        }
+
+        let mut delete = tx
+            .prepare(&format!(
+                "DELETE FROM \"{}\" WHERE \"EntryNumber\" IS ?1 AND \"Document\" IS ?2",
+                table
+            ))
+            .map_err(sql_error)?;
+        let mut seen = BTreeSet::new();
+        for t in transactions.iter().filter(|t| !is_blank(t.data.get("EntryNumber"))) {
+            let (entry, document) = (t.data.get("EntryNumber"), t.data.get("Document"));
+            if seen.insert((csv_cell(entry), csv_cell(document))) {
+                delete
+                    .execute([sqlite_value(entry), sqlite_value(document)])
+                    .map_err(sql_error)?;
+            }
+        }
+    }
+
+    {
+        let quoted: Vec<String> = columns.iter().map(|column| format!("\"{}\"", column)).collect();
+        let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+        let mut insert = tx
+            .prepare(&format!(
+                "INSERT INTO \"{}\" ({}) VALUES ({})",
+                table,
+                quoted.join(", "),
+                placeholders.join(", ")
+            ))
+            .map_err(sql_error)?;
+        for t in transactions {
+            let values = columns.iter().map(|column| {
+                if *column == SOURCE_DIVISION_COLUMN {
+                    return t
+                        .source_division
+                        .map(|d| rusqlite::types::Value::Integer(d as i64))
+                        .unwrap_or(rusqlite::types::Value::Null);
+                }
+                sqlite_value(t.data.get(*column))
+            });
+            insert
+                .execute(rusqlite::params_from_iter(values))
+                .map_err(sql_error)?;
+        }
+    }
+    tx.commit().map_err(sql_error)?;
+    Ok(transactions.len())
+}
+
+/// Parquet column type of a transaction field: amounts as doubles, dates as UTC
+/// millisecond timestamps, counters as 64-bit integers and everything else as strings
+fn parquet_type(field: &str) -> arrow::datatypes::DataType {
+    use arrow::datatypes::{DataType, TimeUnit};
+    match field_kind(field) {
+        FieldKind::Amount => DataType::Float64,
+        FieldKind::Date => DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        FieldKind::Text if INTEGER_FIELDS.contains(&field) || field == SOURCE_DIVISION_COLUMN => DataType::Int64,
+        FieldKind::Text => DataType::Utf8,
+    }
+}
+
+/// Milliseconds since the epoch of an RFC3339 date or a raw `/Date(ms)/` string
+fn parquet_timestamp(value: &str) -> Option<i64> {
+    if let Some(captures) = date_regex().captures(value) {
+        return captures[1].parse().ok();
+    }
+    chrono::DateTime::parse_from_rfc3339(value.trim())
+        .ok()
+        .map(|dt| dt.timestamp_millis())
+}
+
+/// Writes transactions to a Snappy compressed Parquet file, one column per field
+/// present in any row (see `ordered_fields`) plus `source_division`. Rows without
+/// a field get a null. Values that don't fit their column's type (e.g. text in an
+/// amount field) fail the export rather than being dropped. Returns the row count.
+#[tauri::command]
+async fn export_transactions_parquet(transactions: Vec<Transaction>, path: String) -> Result<usize, String> {
+    let result = write_parquet(&transactions, &path);
+    record_errors("export_transactions_parquet", result).await
+}
+
+fn write_parquet(transactions: &[Transaction], path: &str) -> Result<usize, String> {
+    use arrow::array::{ArrayRef, Float64Array, Int64Array, StringArray, TimestampMillisecondArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+
+    let mut columns = ordered_fields(transactions);
+    if columns.is_empty() {
+        columns = TRANSACTION_FIELDS.split(',').collect();
+    }
+    columns.push(SOURCE_DIVISION_COLUMN);
+
+    let value_of = |t: &Transaction, column: &str| -> Option<serde_json::Value> {
+        if column == SOURCE_DIVISION_COLUMN {
+            return t.source_division.map(serde_json::Value::from);
+        }
+        t.data.get(column).filter(|v| !v.is_null()).cloned()
+    };
+    let mismatch = |column: &str, row: usize, value: &serde_json::Value| {
+        format!("Failed to write Parquet: {} in row {} is not a valid value: {}", column, row + 1, value)
+    };
+
+    let mut fields = Vec::with_capacity(columns.len());
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len());
+    for column in &columns {
+        let data_type = parquet_type(column);
+        let values = transactions.iter().map(|t| value_of(t, column));
+        let array: ArrayRef = match &data_type {
+            DataType::Float64 => Arc::new(
+                values
+                    .enumerate()
+                    .map(|(row, value)| match value {
+                        None => Ok(None),
+                        Some(serde_json::Value::Number(n)) => Ok(n.as_f64()),
+                        Some(serde_json::Value::String(s)) if s.trim().is_empty() => Ok(None),
+                        Some(serde_json::Value::String(s)) => s
+                            .trim()
+                            .parse()
+                            .map(Some)
+                            .map_err(|_| mismatch(column, row, &serde_json::Value::String(s))),
+                        Some(other) => Err(mismatch(column, row, &other)),
+                    })
+                    .collect::<Result<Float64Array, String>>()?,
+            ),
+            DataType::Int64 => Arc::new(
+                values
+                    .enumerate()
+                    .map(|(row, value)| match value {
+                        None => Ok(None),
+                        Some(serde_json::Value::Number(n)) if n.is_i64() => Ok(n.as_i64()),
+                        Some(serde_json::Value::String(s)) if s.trim().is_empty() => Ok(None),
+                        Some(serde_json::Value::String(s)) => s
+                            .trim()
+                            .parse()
+                            .map(Some)
+                            .map_err(|_| mismatch(column, row, &serde_json::Value::String(s))),
+                        Some(other) => Err(mismatch(column, row, &other)),
+                    })
+                    .collect::<Result<Int64Array, String>>()?,
+            ),
+            DataType::Timestamp(_, _) => Arc::new(
+                values
+                    .enumerate()
+                    .map(|(row, value)| match value {
+                        None => Ok(None),
+                        Some(serde_json::Value::String(s)) if s.trim().is_empty() => Ok(None),
+                        Some(serde_json::Value::String(s)) => {
+                            parquet_timestamp(&s).map(Some).ok_or_else(|| mismatch(column, row, &serde_json::Value::String(s)))
+                        }
+                        Some(other) => Err(mismatch(column, row, &other)),
+                    })
+                    .collect::<Result<TimestampMillisecondArray, String>>()?
+                    .with_timezone("UTC"),
+            ),
+            _ => Arc::new(
+                values
+                    .map(|value| match value {
+                        None => None,
+                        Some(serde_json::Value::String(s)) => Some(s),
+                        Some(other) => Some(other.to_string()),
+                    })
+                    .collect::<StringArray>(),
+            ),
+        };
+        fields.push(Field::new(*column, data_type, true));
+        arrays.push(array);
+    }
+
+    let write_error = |e: parquet::errors::ParquetError| format!("Failed to write Parquet: {}", e);
+    let schema = Arc::new(Schema::new(fields));
+    let batch = arrow::record_batch::RecordBatch::try_new(schema.clone(), arrays)
+        .map_err(|e| format!("Failed to write Parquet: {}", e))?;
+    let file = fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    let properties = parquet::file::properties::WriterProperties::builder()
+        .set_compression(parquet::basic::Compression::SNAPPY)
+        .build();
+    let mut writer = parquet::arrow::ArrowWriter::try_new(file, schema, Some(properties)).map_err(write_error)?;
+    writer.write(&batch).map_err(write_error)?;
+    writer.close().map_err(write_error)?;
+    Ok(transactions.len())
+}
+
+/// Every field present in any of the rows: the known transaction fields in their
+/// usual order, then the rest alphabetically
+fn ordered_fields(transactions: &[Transaction]) -> Vec<&str> {
+    let mut extra: Vec<&str> = transactions
+        .iter()
+        .flat_map(|t| t.data.keys())
+        .map(String::as_str)
+        .filter(|key| !TRANSACTION_FIELDS.split(',').any(|field| field == *key))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let mut fields: Vec<&str> = TRANSACTION_FIELDS
+        .split(',')
+        .filter(|field| transactions.iter().any(|t| t.data.contains_key(*field)))
+        .collect();
+    fields.append(&mut extra);
+    fields
+}
+
+/// Column list for a result set whose rows may carry different keys. Without any
+/// rows the default transaction fields are returned.
+#[tauri::command]
+fn collect_fields(transactions: Vec<Transaction>) -> Vec<String> {
+    if transactions.is_empty() {
+        return TRANSACTION_FIELDS.split(',').map(String::from).collect();
+    }
+    ordered_fields(&transactions).into_iter().map(String::from).collect()
+}
+
+#[derive(Debug, Serialize)]
+struct FieldInfo {
+    name: String,
+    kind: FieldKind,
+}
+
+/// The `$select`able fields of an endpoint with their types, for building field
+/// pickers. Fails for endpoints without a known field set.
+#[tauri::command]
+fn get_endpoint_fields(endpoint: String) -> Result<Vec<FieldInfo>, String> {
+    let fields = selectable_fields(&endpoint)
+        .ok_or_else(|| format!("No known fields for {}", endpoint))?;
+    Ok(fields
+        .into_iter()
+        .map(|name| FieldInfo {
+            name: name.to_string(),
+            kind: field_kind(name),
+        })
+        .collect())
+}
+
+fn csv_cell(value: Option<&serde_json::Value>) -> String {
+    match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DistinctValues {
+    values: Vec<String>,
+    /// More distinct values exist than were returned
+    truncated: bool,
+}
+
+const DEFAULT_DISTINCT_LIMIT: usize = 1000;
+
+/// Returned from the page callback to stop paging once the limit is exceeded
+const DISTINCT_LIMIT_REACHED: &str = "distinct value limit reached";
+
+/// Distinct values of one field, sorted, e.g. for filter dropdowns. Only that field
+/// is selected while paging (Exact doesn't support `$apply=groupby`). Stops once
+/// more than `limit` (default 1000) values were seen and reports the list as
+/// truncated. Empty values are left out.
+#[tauri::command]
+async fn distinct_field_values(
+    app: tauri::AppHandle,
+    division: i32,
+    endpoint: String,
+    field: String,
+    filter: Option<String>,
+    limit: Option<usize>,
+) -> Result<DistinctValues, String> {
+    let limit = limit.unwrap_or(DEFAULT_DISTINCT_LIMIT).max(1);
+    let request = FetchRequest {
+        division,
+        endpoint,
+        select: Some(vec![field.clone()]),
+        filter,
+        silent: true,
+        ..Default::default()
+    };
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let mut values = BTreeSet::new();
+    let result = fetch_pages_waiting(&app, &operation_id, &request, &cancel_flag, |rows, _| {
+        for row in rows {
+            let value = csv_cell(row.data.get(&field));
+            if !value.trim().is_empty() {
+                values.insert(value);
+            }
+        }
+        if values.len() > limit {
+            return Err(DISTINCT_LIMIT_REACHED.to_string());
+        }
+        Ok(())
+    })
+    .await;
+    finish_operation(&operation_id).await;
+
+    let result = match result {
+        Ok(_) => Ok(false),
+        Err(e) if e == DISTINCT_LIMIT_REACHED => Ok(true),
+        Err(e) => Err(e),
+    }
+    .map(|truncated| DistinctValues {
+        values: values.into_iter().take(limit).collect(),
+        truncated,
+    });
+    record_errors("distinct_field_values", result).await
+}
+
+/// Fetches transaction lines straight into a CSV file, writing every page as it
+/// arrives instead of collecting the whole set first. The columns are the
+/// transaction fields in their usual order, or those of `column_spec`. A cancelled
+/// or failed export deletes the incomplete file. Returns the number of rows written.
+#[tauri::command]
+async fn fetch_and_export_csv(
+    app: tauri::AppHandle,
+    division: i32,
+    filter: Option<String>,
+    path: String,
+    column_spec: Option<Vec<ColumnSpec>>,
+) -> Result<usize, String> {
+    let request = FetchRequest::transaction_lines(division, filter);
+    let fields: Vec<&str> = TRANSACTION_FIELDS.split(',').collect();
+    let (fields, headers) = match column_spec {
+        Some(spec) => match resolve_columns(spec, &fields) {
+            Ok(columns) => columns,
+            Err(e) => return record_errors("fetch_and_export_csv", Err(e)).await,
+        },
+        None => (
+            fields.iter().map(|f| f.to_string()).collect(),
+            fields.iter().map(|f| f.to_string()).collect(),
+        ),
+    };
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = stream_csv(&app, &operation_id, &request, &fields, &headers, &path, &cancel_flag).await;
+    finish_operation(&operation_id).await;
+    if let Err(e) = &result {
+        let _ = fs::remove_file(&path);
+        handle_division_access_lost(&app, &operation_id, e).await;
+    }
+    record_errors("fetch_and_export_csv", result).await
+}
+
+async fn stream_csv(
+    app: &tauri::AppHandle,
+    operation_id: &str,
+    request: &FetchRequest,
+    fields: &[String],
+    headers: &[String],
+    path: &str,
+    cancel_flag: &AtomicBool,
+) -> Result<usize, String> {
+    let mut writer = csv::Writer::from_path(path).map_err(|e| format!("Failed to create CSV file: {}", e))?;
+    writer
+        .write_record(headers)
+        .map_err(|e| format!("Failed to write CSV: {}", e))?;
+
+    let written = fetch_pages(app, operation_id, request, cancel_flag, None, |rows, _| {
+        for row in rows {
+            writer
+                .write_record(fields.iter().map(|field| csv_cell(row.data.get(field))))
+                .map_err(|e| format!("Failed to write CSV: {}", e))?;
+        }
+        Ok(())
+    })
+    .await?;
+    writer.flush().map_err(|e| format!("Failed to write CSV: {}", e))?;
+    Ok(written.rows)
+}
+
+/// Reads a CSV written by `export_transactions_csv` back into transactions for
+/// offline use. Empty cells become absent keys, amounts and counters become numbers
+/// again and dates are kept as the strings that were exported.
+#[tauri::command]
+async fn import_transactions_csv(path: String) -> Result<Vec<Transaction>, String> {
+    record_errors("import_transactions_csv", read_transactions_csv(&path)).await
+}
+
+fn read_transactions_csv(path: &str) -> Result<Vec<Transaction>, String> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| format!("Failed to read CSV file: {}", e))?;
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| format!("Failed to parse CSV header: {}", e))?
+        .iter()
+        .map(String::from)
+        .collect();
+
+    let is_known = |header: &str| {
+        header == SOURCE_DIVISION_COLUMN
+            || TRANSACTION_FIELDS.split(',').any(|field| field == header)
+            || field_kind(header) != FieldKind::Text
+    };
+    if !headers.iter().any(|header| is_known(header)) {
+        return Err("Failed to parse CSV: none of the columns are transaction fields".to_string());
+    }
+    let unknown: Vec<&str> = headers
+        .iter()
+        .map(String::as_str)
+        .filter(|header| !is_known(header))
+        .collect();
+    if !unknown.is_empty() {
+        eprintln!("[IMPORT] Unknown columns in {}: {}", path, unknown.join(", "));
+    }
+
+    let mut transactions = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| format!("Failed to parse CSV: {}", e))?;
+        let mut transaction = Transaction {
+            data: HashMap::new(),
+            source_division: None,
+        };
+        for (header, cell) in headers.iter().zip(record.iter()) {
+            if cell.is_empty() {
+                continue;
+            }
+            if header == SOURCE_DIVISION_COLUMN {
+                transaction.source_division = cell.trim().parse().ok();
+                continue;
+            }
+            let text = serde_json::Value::String(cell.to_string());
+            let value = if field_kind(header) == FieldKind::Amount {
+                normalize_amount(text)
+            } else if INTEGER_FIELDS.contains(&header.as_str()) {
+                cell.trim().parse::<i64>().map(serde_json::Value::from).unwrap_or(text)
+            } else {
+                text
+            };
+            transaction.data.insert(header.clone(), value);
+        }
+        transactions.push(transaction);
+    }
+    Ok(transactions)
+}
+
+#[tauri::command]
+async fn export_divisions_csv(divisions: Vec<Division>, path: String) -> Result<(), String> {
+    let rows = divisions.into_iter().map(|d| {
+        vec![
+            d.Code.to_string(),
+            d.CustomerCode.unwrap_or_default(),
+            d.CustomerName,
+            d.Description,
+        ]
+    });
+    let result = write_csv(&path, &["Code", "CustomerCode", "CustomerName", "Description"], rows);
+    record_errors("export_divisions_csv", result).await
+}
+
+/// Forgets the selected division but keeps the session, forcing a re-selection
+#[tauri::command]
+async fn clear_division() -> Result<(), String> {
+    record_errors("clear_division", forget_division().await).await
+}
+
+/// Makes `division` the active division. Lookups cached for the previous one are
+/// dropped; with `warm` the new division's VAT codes, journals, GL classifications,
+/// cost centers and cost units are loaded in the background. `division-switched` is
+/// emitted once the division is ready to use, `warmed` telling whether every lookup
+/// loaded.
+#[tauri::command]
+async fn set_current_division(app: tauri::AppHandle, division: i32, warm: Option<bool>) -> Result<(), String> {
+    record_errors("set_current_division", switch_division(division).await).await?;
+
+    if !warm.unwrap_or(true) {
+        let _ = app.emit("division-switched", serde_json::json!({
+            "division": division,
+            "warmed": false
+        }));
+        return Ok(());
+    }
+
+    tauri::async_runtime::spawn(async move {
+        let (operation_id, cancel_flag) = begin_operation().await;
+        let warmed = warm_lookups(&app, &operation_id, division, &cancel_flag).await;
+        finish_operation(&operation_id).await;
+        let _ = app.emit("division-switched", serde_json::json!({
+            "operation_id": operation_id,
+            "division": division,
+            "warmed": warmed
+        }));
+    });
+    Ok(())
+}
+
+/// Loads the lookups enrichment uses into the caches. A failed lookup is logged and
+/// the others still load; returns whether all of them did.
+async fn warm_lookups(app: &tauri::AppHandle, operation_id: &str, division: i32, cancel_flag: &AtomicBool) -> bool {
+    let results = [
+        ("VAT codes", vat_codes(app, operation_id, division, cancel_flag).await.map(drop)),
+        ("journals", journals(app, operation_id, division, cancel_flag).await.map(drop)),
+        ("GL classifications", gl_classifications(app, operation_id, division, cancel_flag).await.map(drop)),
+        ("cost centers", code_list(app, operation_id, division, COST_CENTERS_ENDPOINT, cancel_flag).await.map(drop)),
+        ("cost units", code_list(app, operation_id, division, COST_UNITS_ENDPOINT, cancel_flag).await.map(drop)),
+    ];
+    let mut warmed = true;
+    for (lookup, result) in results {
+        if let Err(e) = result {
+            eprintln!("[DIVISION] Warming {} for {} failed: {}", lookup, division, e);
+            warmed = false;
+        }
+    }
+    warmed
+}
+
+async fn switch_division(division: i32) -> Result<(), String> {
+    let mut state_guard = get_app_state().await?;
+    let state = state_guard.as_mut().ok_or("State not initialized")?;
+    if state.current_division != Some(division) {
+        state.vat_codes.clear();
+        state.code_lists.clear();
+        state.journals.clear();
+        state.projects.clear();
+        state.gl_classifications.clear();
+        state.payment_conditions.clear();
+        state.custom_fields.clear();
+        state.users.clear();
+        state.cached_transactions.clear();
+        state.cached_at = None;
+    }
+    state.current_division = Some(division);
+    state.save_tokens()
+}
+
+async fn forget_division() -> Result<(), String> {
+    let mut state_guard = get_app_state().await?;
+    let state = state_guard.as_mut().ok_or("State not initialized")?;
+    state.current_division = None;
+    state.save_tokens()
+}
+
+const SETTINGS_EXPORT_VERSION: u32 = 1;
+
+/// Portable, secret-free snapshot of the settings for setting up another machine
+#[derive(Debug, Serialize, Deserialize)]
+struct SettingsExport {
+    version: u32,
+    app_version: String,
+    /// Informational only, the API base is fixed at build time
+    api: String,
+    config: Config,
+    saved_queries: Vec<SavedQuery>,
+}
+
+#[tauri::command]
+async fn export_settings(path: String) -> Result<(), String> {
+    record_errors("export_settings", write_settings_export(&path).await).await
+}
+
+async fn write_settings_export(path: &str) -> Result<(), String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    let export = SettingsExport {
+        version: SETTINGS_EXPORT_VERSION,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        api: state.api.clone(),
+        config: state.config.redacted(),
+        saved_queries: state.load_saved_queries()?,
+    };
+    fs::write(path, serde_json::to_string_pretty(&export).unwrap())
+        .map_err(|e| format!("Failed to save settings: {}", e))
+}
+
+/// Merges an exported settings file into the local config and saved queries.
+/// Secret-related settings in the file are ignored.
+#[tauri::command]
+async fn import_settings(path: String) -> Result<(), String> {
+    record_errors("import_settings", read_settings_export(&path).await).await
+}
+
+async fn read_settings_export(path: &str) -> Result<(), String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read settings: {}", e))?;
+    let export: SettingsExport =
+        serde_json::from_str(&content).map_err(|e| format!("Failed to parse settings: {}", e))?;
+    if export.version != SETTINGS_EXPORT_VERSION {
+        return Err(format!(
+            "Unsupported settings version {} (expected {})",
+            export.version, SETTINGS_EXPORT_VERSION
+        ));
+    }
+    for query in &export.saved_queries {
+        validate_endpoint(&query.endpoint)?;
+    }
+
+    let mut state_guard = get_app_state().await?;
+    let state = state_guard.as_mut().ok_or("State not initialized")?;
+
+    let config = state.config.merged_with(&export.config.redacted())?;
+    config.save(&state.data_dir)?;
+    state.http_client = build_data_client(&config)?;
+    state.config = config;
+
+    let mut queries = state.load_saved_queries()?;
+    for query in export.saved_queries {
+        queries.retain(|q| q.name != query.name);
+        queries.push(query);
+    }
+    queries.sort_by(|a, b| a.name.cmp(&b.name));
+    state.save_saved_queries(&queries)
+}
+
+#[derive(Debug, Serialize)]
+struct ConnectivityStatus {
+    /// The API host name resolved, so there is some network
+    online: bool,
+    /// A TCP connection to the API host succeeded
+    api_reachable: bool,
+}
+
+const CONNECTIVITY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Cheap reachability probe (DNS lookup plus a TCP connect, no HTTP or auth) for
+/// the API host, so the UI can show an offline banner instead of failing calls
+#[tauri::command]
+async fn connectivity_status() -> Result<ConnectivityStatus, String> {
+    let (operation_id, cancel_flag) = begin_operation().await;
+    let result = probe_connectivity(&cancel_flag).await;
+    finish_operation(&operation_id).await;
+    record_errors("connectivity_status", result).await
+}
+
+async fn probe_connectivity(cancel_flag: &AtomicBool) -> Result<ConnectivityStatus, String> {
+    let api = {
+        let state_guard = get_app_state().await?;
+        let state = state_guard.as_ref().ok_or("State not initialized")?;
+        state.api.clone()
+    };
+    let url = reqwest::Url::parse(&api).map_err(|e| format!("Invalid API URL: {}", e))?;
+    let host = url.host_str().ok_or("API URL has no host")?.to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let probe = async {
+        let addresses: Vec<std::net::SocketAddr> =
+            match tokio::time::timeout(CONNECTIVITY_TIMEOUT, tokio::net::lookup_host((host.as_str(), port))).await {
+                Ok(Ok(addresses)) => addresses.collect(),
+                _ => Vec::new(),
+            };
+        let mut api_reachable = false;
+        for address in &addresses {
+            if let Ok(Ok(_)) = tokio::time::timeout(CONNECTIVITY_TIMEOUT, tokio::net::TcpStream::connect(address)).await {
+                api_reachable = true;
+                break;
+            }
+        }
+        ConnectivityStatus {
+            online: !addresses.is_empty(),
+            api_reachable,
+        }
+    };
+
+    tokio::select! {
+        status = probe => Ok(status),
+        _ = wait_for_cancel(cancel_flag) => Err("Operation cancelled by user".to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct LimitResets {
+    minutely: Option<String>,
+    daily: Option<String>,
+}
+
+/// Exact's rate limits for this app as reported by the last response that carried them
+#[derive(Debug, Clone, Default, Serialize)]
+struct ApiLimits {
+    minutely_limit: Option<u64>,
+    minutely_remaining: Option<u64>,
+    daily_limit: Option<u64>,
+    daily_remaining: Option<u64>,
+    reset_times: LimitResets,
+    observed_at: String,
+}
+
+/// Limits from the most recent response with rate limit headers
+static API_LIMITS: std::sync::Mutex<Option<(std::time::Instant, ApiLimits)>> = std::sync::Mutex::new(None);
+
+/// Observed limits younger than this are returned without a new request
+const API_LIMITS_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Records the `X-RateLimit-*` headers Exact sends with data responses
+fn observe_limits(headers: &reqwest::header::HeaderMap) {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+    let number = |name: &str| header(name).and_then(|v| v.parse::<u64>().ok());
+    // Resets are milliseconds since the epoch
+    let reset = |name: &str| {
+        header(name)
+            .and_then(|v| v.parse::<i64>().ok())
+            .and_then(chrono::DateTime::from_timestamp_millis)
+            .map(|at| at.to_rfc3339())
+    };
+    let limits = ApiLimits {
+        minutely_limit: number("X-RateLimit-Minutely-Limit"),
+        minutely_remaining: number("X-RateLimit-Minutely-Remaining"),
+        daily_limit: number("X-RateLimit-Limit"),
+        daily_remaining: number("X-RateLimit-Remaining"),
+        reset_times: LimitResets {
+            minutely: reset("X-RateLimit-Minutely-Reset"),
+            daily: reset("X-RateLimit-Reset"),
+        },
+        observed_at: chrono::Utc::now().to_rfc3339(),
+    };
+    if limits.minutely_limit.is_none() && limits.daily_limit.is_none() {
+        return;
+    }
+    *API_LIMITS.lock().unwrap() = Some((std::time::Instant::now(), limits));
+}
+
+/// The app's minutely and daily API quota and what is left of it. Exact has no
+/// endpoint for this, so it comes from the headers of recent responses; without
+/// one from the last minute (or with `refresh`) a `$top=1` request is made.
+#[tauri::command]
+async fn get_api_limits(refresh: Option<bool>) -> Result<ApiLimits, String> {
+    let cached = API_LIMITS
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(|(at, _)| at.elapsed() < API_LIMITS_MAX_AGE && !refresh.unwrap_or(false));
+    if let Some((_, limits)) = cached {
+        return Ok(limits);
+    }
+    let result = async {
+        let mut state_guard = get_app_state().await?;
+        let state = state_guard.as_mut().ok_or("State not initialized")?;
+        state.refresh_token().await?;
+        let division = state.discover_division().await?;
+        let before = API_LIMITS.lock().unwrap().as_ref().map(|(at, _)| *at);
+        state
+            .get(&format!("/v1/{}/system/Divisions?$select=Code&$top=1", division))
+            .await?;
+        match API_LIMITS.lock().unwrap().clone() {
+            Some((at, limits)) if Some(at) != before => Ok(limits),
+            _ => Err("Exact did not report rate limits for this app".to_string()),
+        }
+    }
+    .await;
+    record_errors("get_api_limits", result).await
+}
+
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 3;
+
+/// Caps data requests across every running operation, so parallel divisions,
+/// enrichment lookups and exports together can't fan out past the rate limit.
+/// A request holds its slot until its body has been read.
+struct RequestLimiter {
+    permits: tokio::sync::Semaphore,
+    max: usize,
+}
+
+static REQUEST_LIMITER: OnceLock<RequestLimiter> = OnceLock::new();
 
-    // Fetch and store the current division
-    if let Err(e) = state.fetch_current_division().await {
-        eprintln!("[AUTH] Warning: Failed to fetch current division: {}", e);
-        // Don't fail authentication if division fetch fails, but log it
+/// One of the `RequestLimiter` slots; `None` before the limiter is set up
+type RequestSlot = Option<tokio::sync::SemaphorePermit<'static>>;
+
+/// Waits for a free request slot
+async fn request_slot() -> Result<RequestSlot, String> {
+    match REQUEST_LIMITER.get() {
+        Some(limiter) => limiter
+            .permits
+            .acquire()
+            .await
+            .map(Some)
+            .map_err(|e| format!("HTTP request failed: {}", e)),
+        None => Ok(None),
     }
+}
 
-    state.save_tokens()?;
+#[derive(Debug, Serialize)]
+struct RequestConcurrency {
+    in_flight: usize,
+    max: usize,
+}
 
-    Ok(())
+/// Data requests currently in flight. Reads no app state, so it answers while a
+/// long operation holds the state lock.
+#[tauri::command]
+async fn get_requests_in_flight() -> Result<RequestConcurrency, String> {
+    Ok(match REQUEST_LIMITER.get() {
+        Some(limiter) => RequestConcurrency {
+            in_flight: limiter.max - limiter.permits.available_permits(),
+            max: limiter.max,
+        },
+        None => RequestConcurrency {
+            in_flight: 0,
+            max: DEFAULT_MAX_CONCURRENT_REQUESTS,
+        },
+    })
 }
 
+#[derive(Debug, Serialize)]
+struct PingResult {
+    /// TCP connect time to the API host (includes DNS), if it could be measured
+    connect_ms: Option<u64>,
+    auth_ms: u64,
+    fetch_ms: u64,
+}
+
+/// Times a forced token refresh and a minimal `$top=1` fetch separately, so
+/// users can tell whether slowness is in auth or in data calls
 #[tauri::command]
-async fn get_divisions() -> Result<Vec<Division>, String> {
+async fn ping_api() -> Result<PingResult, String> {
+    record_errors("ping_api", measure_latency().await).await
+}
+
+async fn measure_latency() -> Result<PingResult, String> {
     let mut state_guard = get_app_state().await?;
     let state = state_guard.as_mut().ok_or("State not initialized")?;
 
+    let connect_ms = match reqwest::Url::parse(&state.api) {
+        Ok(url) => match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => {
+                let started = std::time::Instant::now();
+                tokio::net::TcpStream::connect((host, port))
+                    .await
+                    .ok()
+                    .map(|_| started.elapsed().as_millis() as u64)
+            }
+            _ => None,
+        },
+        Err(_) => None,
+    };
+
+    let started = std::time::Instant::now();
+    state.refresh_at = 0;
     state.refresh_token().await?;
+    let auth_ms = started.elapsed().as_millis() as u64;
 
     let division = state.current_division.ok_or("No current division found. Please authenticate first.")?;
-    let attributes = "Code,Customer,CustomerCode,CustomerName,Description";
-    let path = format!(
-        "/v1/{}/system/Divisions?$select={}",
-        division, attributes
-    );
+    let started = std::time::Instant::now();
+    state
+        .get(&format!("/v1/{}/system/Divisions?$select=Code&$top=1", division))
+        .await?;
+    let fetch_ms = started.elapsed().as_millis() as u64;
 
-    // Create and set cancellation flag
-    let cancel_flag = Arc::new(AtomicBool::new(false));
-    {
-        let mut flag_guard = CANCELLATION_FLAG.lock().await;
-        *flag_guard = Some(cancel_flag.clone());
+    Ok(PingResult {
+        connect_ms,
+        auth_ms,
+        fetch_ms,
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    app_version: String,
+    tauri_version: String,
+    api_base: String,
+    /// Exact doesn't publish a version number; this is the REST path version we call
+    api_path_version: String,
+    user_agent: String,
+}
+
+/// Checks that the stored tokens exist, parse and carry the fields `load_tokens`
+/// needs, to explain why the app came up logged out
+#[tauri::command]
+async fn verify_token_store() -> Result<TokenStoreStatus, String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    let status = verify_store(state.token_store.as_ref());
+    if let Some(problem) = &status.problem {
+        eprintln!("[TOKENS] {}: {}", status.store, problem);
     }
+    Ok(status)
+}
 
-    let mut all_results = Vec::new();
-    let mut next_path = Some(path);
+/// The most recent token refresh this session, `None` before the first one
+#[tauri::command]
+async fn last_refresh_info() -> Result<Option<RefreshInfo>, String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    Ok(state.last_refresh.clone())
+}
 
-    while let Some(path) = next_path {
-        // Check for cancellation
-        if cancel_flag.load(Ordering::Relaxed) {
-            // Clear cancellation flag
-            {
-                let mut flag_guard = CANCELLATION_FLAG.lock().await;
-                *flag_guard = None;
-            }
-            return Err("Operation cancelled by user".to_string());
-        }
+/// Re-applies user-only permissions to the data directory and token files, for
+/// when they were loosened after startup. Returns what was changed.
+#[tauri::command]
+async fn fix_permissions() -> Result<Vec<PermissionFix>, String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    secure_data_dir(&state.data_dir)
+}
 
-        let response = state.get(&path).await?;
-        let api_response: ApiResponse<Division> =
-            serde_json::from_value(response).map_err(|e| format!("Failed to parse divisions: {}", e))?;
+/// Pins down the build and endpoint a user is on, for support triage
+#[tauri::command]
+async fn version_info() -> Result<VersionInfo, String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    Ok(VersionInfo {
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        tauri_version: tauri::VERSION.to_string(),
+        api_base: state.api.clone(),
+        api_path_version: "v1".to_string(),
+        user_agent: state.config.user_agent(),
+    })
+}
 
-        all_results.extend(api_response.d.results);
+#[derive(Debug, Serialize)]
+struct AppRegistrationInfo {
+    client_id: String,
+    redirect_uri: String,
+    api_base: String,
+    /// Exact country site the API base points at, e.g. `nl`, `be` or `uk`
+    region: Option<String>,
+    /// `None` when login doesn't ask for scopes and the registration's own apply
+    scopes: Option<Vec<String>>,
+    /// Whether a client secret was found; the secret itself is never returned
+    client_secret_configured: bool,
+}
 
-        next_path = api_response.d.__next.map(|next| {
-            next.strip_prefix(&state.api)
-                .unwrap_or(&next)
-                .to_string()
-        });
+/// Whether an absolute `__next` link may be requested with the API's token: the
+/// API's own origin, or another https host of the same Exact country site (e.g.
+/// `*.exactonline.nl` for `start.exactonline.nl`). A gateway API base that isn't an
+/// Exact host only allows its own origin.
+fn next_page_allowed(api: &reqwest::Url, target: &reqwest::Url) -> bool {
+    if target.origin() == api.origin() {
+        return true;
     }
+    // The suffix must equal the configured API's, so `exactonline.nl.example.com`
+    // and the like don't pass
+    let exact_site = |url: &reqwest::Url| {
+        let host = url.host_str()?.to_lowercase();
+        host.split_once(".exactonline.").map(|(_, suffix)| suffix.to_string())
+    };
+    target.scheme() == "https"
+        && target.port().is_none()
+        && exact_site(api).is_some_and(|site| exact_site(target).as_deref() == Some(site.as_str()))
+}
 
-    // Clear cancellation flag on success
-    {
-        let mut flag_guard = CANCELLATION_FLAG.lock().await;
-        *flag_guard = None;
-    }
+/// The Exact country site of an API base such as `https://start.exactonline.nl/api`
+fn exact_region(api: &str) -> Option<String> {
+    let url = reqwest::Url::parse(api).ok()?;
+    let suffix = url.host_str()?.split_once(".exactonline.")?.1.to_lowercase();
+    Some(match suffix.as_str() {
+        "co.uk" => "uk".to_string(),
+        "com" => "us".to_string(),
+        _ => suffix,
+    })
+}
 
-    all_results.sort_by(|a, b| {
-        format!("{}{}", a.CustomerName, a.Description)
-            .cmp(&format!("{}{}", b.CustomerName, b.Description))
-    });
+/// What the app is configured to log in with, for the settings screen and
+/// support diagnostics. Leaves out the client secret.
+#[tauri::command]
+async fn app_registration_info() -> Result<AppRegistrationInfo, String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    Ok(AppRegistrationInfo {
+        client_id: state.client_id.clone(),
+        redirect_uri: state.redirect_uri.clone(),
+        api_base: state.api.clone(),
+        region: exact_region(&state.api),
+        scopes: state.config.scopes.clone().filter(|scopes| !scopes.is_empty()),
+        client_secret_configured: !state.client_secret.is_empty(),
+    })
+}
 
-    Ok(all_results)
+#[derive(Debug, Serialize)]
+struct DivisionSummary {
+    /// `None` for rows that carry no division at all
+    division: Option<i32>,
+    /// Description from the last division listing, if the division was in it
+    name: Option<String>,
+    count: usize,
 }
 
+/// Counts transactions per division, e.g. to check a multi-division fetch covered
+/// everything. Rows are attributed by `source_division`, then by their `Division`
+/// field.
 #[tauri::command]
-async fn get_transactions(
-    app: tauri::AppHandle,
-    division: i32,
-    filter: Option<String>,
-) -> Result<Vec<Transaction>, String> {
-    let mut state_guard = get_app_state().await?;
-    let state = state_guard.as_mut().ok_or("State not initialized")?;
+async fn summarize_divisions(transactions: Vec<Transaction>) -> Result<Vec<DivisionSummary>, String> {
+    let mut counts: BTreeMap<Option<i32>, usize> = BTreeMap::new();
+    for transaction in &transactions {
+        let division = transaction.source_division.or_else(|| {
+            transaction
+                .data
+                .get("Division")
+                .and_then(parse_count)
+                .map(|division| division as i32)
+        });
+        *counts.entry(division).or_default() += 1;
+    }
 
-    state.refresh_token().await?;
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    Ok(counts
+        .into_iter()
+        .map(|(division, count)| DivisionSummary {
+            division,
+            name: division.and_then(|d| state.division_names.get(&d).cloned()),
+            count,
+        })
+        .collect())
+}
 
-    let attributes = "AccountCode,AccountName,AmountDC,AmountFC,AmountVATBaseFC,AmountVATFC,AssetCode,AssetDescription,CostCenter,CostCenterDescription,CostUnit,CostUnitDescription,CreatorFullName,Currency,CustomField,Description,Division,Document,DocumentNumber,DocumentSubject,DueDate,EntryNumber,ExchangeRate,ExternalLinkDescription,ExternalLinkReference,ExtraDutyAmountFC,ExtraDutyPercentage,FinancialPeriod,FinancialYear,GLAccountCode,GLAccountDescription,InvoiceNumber,Item,ItemCode,ItemDescription,JournalCode,JournalDescription,LineType,Modified,ModifierFullName,Notes,OrderNumber,PaymentDiscountAmount,PaymentReference,Project,ProjectCode,ProjectDescription,Quantity,SerialNumber,ShopOrder,Status,Subscription,SubscriptionDescription,TrackingNumber,TrackingNumberDescription,Type,VATCode,VATCodeDescription,VATPercentage,VATType,YourRef";
+#[derive(Debug, Serialize, Deserialize)]
+struct AggRow {
+    /// The `group_by` fields and their value for this group (`null` when absent)
+    group: BTreeMap<String, serde_json::Value>,
+    count: usize,
+    /// Exact decimal sums, serialized as strings so no precision is lost
+    sums: BTreeMap<String, rust_decimal::Decimal>,
+}
 
-    let mut filter_str = String::new();
-    if let Some(f) = filter {
-        if !f.trim().is_empty() {
-            filter_str = format!("&$filter={}", urlencoding::encode(&f));
+/// Groups transactions by `group_by` and sums `sum_fields` per group. Sums use
+/// decimal arithmetic, so totals of many cent amounts don't drift like floats.
+#[tauri::command]
+fn aggregate_transactions(
+    transactions: Vec<Transaction>,
+    group_by: Vec<String>,
+    sum_fields: Vec<String>,
+) -> Result<Vec<AggRow>, String> {
+    let mut groups: BTreeMap<Vec<String>, AggRow> = BTreeMap::new();
+    for transaction in &transactions {
+        let values: Vec<serde_json::Value> = group_by
+            .iter()
+            .map(|field| transaction.data.get(field).cloned().unwrap_or(serde_json::Value::Null))
+            .collect();
+        let key = values.iter().map(|v| v.to_string()).collect();
+        let row = groups.entry(key).or_insert_with(|| AggRow {
+            group: group_by.iter().cloned().zip(values).collect(),
+            count: 0,
+            sums: sum_fields
+                .iter()
+                .map(|field| (field.clone(), rust_decimal::Decimal::ZERO))
+                .collect(),
+        });
+        row.count += 1;
+        for field in &sum_fields {
+            let amount = match transaction.data.get(field) {
+                None | Some(serde_json::Value::Null) => continue,
+                Some(value) => parse_decimal(value)
+                    .ok_or(format!("Failed to parse {} as an amount: {}", field, value))?,
+            };
+            if let Some(sum) = row.sums.get_mut(field) {
+                *sum += amount;
+            }
         }
     }
+    Ok(groups.into_values().collect())
+}
 
-    let path = format!(
-        "/v1/{}/bulk/Financial/TransactionLines?$select={}{}",
-        division, attributes, filter_str
-    );
+#[derive(Debug, Serialize)]
+struct FieldChange {
+    field: String,
+    old: serde_json::Value,
+    new: serde_json::Value,
+    /// Readable summary, e.g. `AmountDC: 100.00 -> 120.50 (+20.50)`
+    description: String,
+}
 
-    // Create and set cancellation flag
-    let cancel_flag = Arc::new(AtomicBool::new(false));
-    {
-        let mut flag_guard = CANCELLATION_FLAG.lock().await;
-        *flag_guard = Some(cancel_flag.clone());
-    }
+#[derive(Debug, Serialize)]
+struct ChangedRow {
+    /// The `key_fields` and their value for this row
+    key: BTreeMap<String, serde_json::Value>,
+    changes: Vec<FieldChange>,
+}
 
-    let mut all_results = Vec::new();
-    let mut next_path = Some(path);
+#[derive(Debug, Serialize)]
+struct TransactionDiff {
+    added: Vec<Transaction>,
+    removed: Vec<Transaction>,
+    changed: Vec<ChangedRow>,
+}
 
-    // First, try to get an estimate of total count
-    let count_path = format!(
-        "/v1/{}/bulk/Financial/TransactionLines/$count{}",
-        division, filter_str
-    );
-    let mut estimated_total: Option<i32> = None;
-    if let Ok(count_response) = state.get(&count_path).await {
-        // Check for cancellation before continuing
-        if cancel_flag.load(Ordering::Relaxed) {
-            let mut flag_guard = CANCELLATION_FLAG.lock().await;
-            *flag_guard = None;
-            return Err("Operation cancelled by user".to_string());
+/// Compares two result sets row by row, matching rows on `key_fields`. Amounts are
+/// compared as decimals, so `100` and `100.0` are not reported as a change.
+#[tauri::command]
+fn diff_transactions(
+    old: Vec<Transaction>,
+    new: Vec<Transaction>,
+    key_fields: Vec<String>,
+) -> Result<TransactionDiff, String> {
+    if key_fields.is_empty() {
+        return Err("At least one key field is required".to_string());
+    }
+    let key_of = |transaction: &Transaction| -> Vec<String> {
+        key_fields
+            .iter()
+            .map(|field| transaction.data.get(field).cloned().unwrap_or(serde_json::Value::Null).to_string())
+            .collect()
+    };
+    let index = |rows: Vec<Transaction>, side: &str| -> Result<BTreeMap<Vec<String>, Transaction>, String> {
+        let mut keyed = BTreeMap::new();
+        for row in rows {
+            let key = key_of(&row);
+            if keyed.contains_key(&key) {
+                return Err(format!("Duplicate key {} in {} rows", key.join(", "), side));
+            }
+            keyed.insert(key, row);
         }
+        Ok(keyed)
+    };
+    let mut old = index(old, "old")?;
+    let new = index(new, "new")?;
 
-        if let Some(count_value) = count_response.as_i64() {
-            estimated_total = Some(count_value as i32);
-            let _ = app.emit("transaction-progress", serde_json::json!({
-                "current": 0,
-                "total": count_value,
-                "message": format!("Found {} transactions, starting fetch...", count_value)
-            }));
+    let mut diff = TransactionDiff {
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+    };
+    for (key, new_row) in new {
+        let Some(old_row) = old.remove(&key) else {
+            diff.added.push(new_row);
+            continue;
+        };
+        let changes = field_changes(&old_row, &new_row, &key_fields);
+        if !changes.is_empty() {
+            diff.changed.push(ChangedRow {
+                key: key_fields
+                    .iter()
+                    .map(|field| {
+                        let value = new_row.data.get(field).cloned().unwrap_or(serde_json::Value::Null);
+                        (field.clone(), value)
+                    })
+                    .collect(),
+                changes,
+            });
         }
     }
+    diff.removed = old.into_values().collect();
+    Ok(diff)
+}
 
-    while let Some(path) = next_path {
-        // Check for cancellation
-        if cancel_flag.load(Ordering::Relaxed) {
-            // Clear cancellation flag
-            {
-                let mut flag_guard = CANCELLATION_FLAG.lock().await;
-                *flag_guard = None;
-            }
-            return Err("Operation cancelled by user".to_string());
-        }
-
-        let response = state.get(&path).await?;
-        let api_response: ApiResponse<serde_json::Value> =
-            serde_json::from_value(response).map_err(|e| format!("Failed to parse transactions: {}", e))?;
-        for result in api_response.d.results {
-            if let serde_json::Value::Object(map) = result {
-                let mut transaction_data = HashMap::new();
-                for (key, value) in map {
-                    match value {
-                        serde_json::Value::String(ref s) => {
-                            if let Some(captures) = regex::Regex::new(r"/Date\((\d+)\)/")
-                                .unwrap()
-                                .captures(s)
-                            {
-                                if let Ok(timestamp_ms) = captures[1].parse::<i64>() {
-                                    let timestamp = timestamp_ms / 1000;
-                                    if let Some(dt) = chrono::DateTime::<chrono::Utc>::from_timestamp(timestamp, 0) {
-                                        transaction_data.insert(key, serde_json::Value::String(dt.to_rfc3339()));
-                                    } else {
-                                        transaction_data.insert(key, value.clone());
-                                    }
-                                } else {
-                                    transaction_data.insert(key, value.clone());
-                                }
-                            } else {
-                                transaction_data.insert(key, value.clone());
-                            }
-                        }
-                        _ => {
-                            if !value.is_object() {
-                                transaction_data.insert(key, value);
-                            }
-                        }
-                    }
+/// Differences between two versions of a row, known transaction fields first
+fn field_changes(old: &Transaction, new: &Transaction, key_fields: &[String]) -> Vec<FieldChange> {
+    let position = |field: &str| TRANSACTION_FIELDS.split(',').position(|f| f == field).unwrap_or(usize::MAX);
+    let mut fields: Vec<&str> = old
+        .data
+        .keys()
+        .chain(new.data.keys())
+        .map(|field| field.as_str())
+        .filter(|field| !key_fields.iter().any(|k| k == field))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    fields.sort_by_key(|field| (position(field), *field));
+
+    let mut changes = Vec::new();
+    for field in fields {
+        let old_value = old.data.get(field).cloned().unwrap_or(serde_json::Value::Null);
+        let new_value = new.data.get(field).cloned().unwrap_or(serde_json::Value::Null);
+        let description = match (field_kind(field), parse_decimal(&old_value), parse_decimal(&new_value)) {
+            (FieldKind::Amount, Some(before), Some(after)) => {
+                if before == after {
+                    continue;
                 }
-                all_results.push(Transaction {
-                    data: transaction_data,
-                });
+                let delta = after - before;
+                let sign = if delta.is_sign_positive() { "+" } else { "" };
+                format!("{}: {} -> {} ({}{})", field, before, after, sign, delta)
             }
-        }
-
-        // Emit progress update
-        let current_count = all_results.len() as i64;
-        let message = if let Some(total) = estimated_total {
-            format!("Fetched {} of {} transactions...", current_count, total)
-        } else {
-            format!("Fetched {} transactions so far...", current_count)
+            _ if old_value == new_value => continue,
+            _ if old_value.is_null() => format!("{}: set to {}", field, csv_cell(Some(&new_value))),
+            _ if new_value.is_null() => format!("{}: cleared (was {})", field, csv_cell(Some(&old_value))),
+            _ => format!(
+                "{}: {} -> {}",
+                field,
+                csv_cell(Some(&old_value)),
+                csv_cell(Some(&new_value))
+            ),
         };
-        let total = estimated_total.map(|t| t as i64).unwrap_or(-1); // Use -1 to indicate unknown
-        let _ = app.emit("transaction-progress", serde_json::json!({
-            "current": current_count,
-            "total": total,
-            "message": message
-        }));
-
-        // Check for cancellation after processing batch
-        if cancel_flag.load(Ordering::Relaxed) {
-            // Clear cancellation flag
-            {
-                let mut flag_guard = CANCELLATION_FLAG.lock().await;
-                *flag_guard = None;
-            }
-            return Err("Operation cancelled by user".to_string());
-        }
-
-        next_path = api_response.d.__next.map(|next| {
-            next.strip_prefix(&state.api)
-                .unwrap_or(&next)
-                .to_string()
+        changes.push(FieldChange {
+            field: field.to_string(),
+            old: old_value,
+            new: new_value,
+            description,
         });
     }
+    changes
+}
 
-    // Clear cancellation flag on success
-    {
-        let mut flag_guard = CANCELLATION_FLAG.lock().await;
-        *flag_guard = None;
-    }
+/// Writes `aggregate_transactions` output as a pivot table: the group-by columns,
+/// then one column per summed field and a `count` column. Sums are written as
+/// exact decimals, never through floats.
+#[tauri::command]
+async fn export_aggregates_csv(agg_rows: Vec<AggRow>, path: String) -> Result<usize, String> {
+    let group_fields: Vec<String> = agg_rows
+        .iter()
+        .flat_map(|row| row.group.keys().cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let sum_fields: Vec<String> = agg_rows
+        .iter()
+        .flat_map(|row| row.sums.keys().cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let mut headers: Vec<&str> = group_fields.iter().chain(&sum_fields).map(String::as_str).collect();
+    headers.push("count");
 
-    Ok(all_results)
+    let rows = agg_rows.iter().map(|row| {
+        let mut cells: Vec<String> = group_fields
+            .iter()
+            .map(|field| csv_cell(row.group.get(field)))
+            .collect();
+        cells.extend(
+            sum_fields
+                .iter()
+                .map(|field| row.sums.get(field).map(|sum| sum.to_string()).unwrap_or_default()),
+        );
+        cells.push(row.count.to_string());
+        cells
+    });
+    let result = write_csv(&path, &headers, rows).map(|_| agg_rows.len());
+    record_errors("export_aggregates_csv", result).await
 }
 
-#[tauri::command]
-async fn is_authenticated() -> bool {
-    if let Ok(state) = get_app_state().await {
-        if let Some(s) = state.as_ref() {
-            return s.access_token.is_some();
-        }
-    }
-    false
+fn parse_decimal(value: &serde_json::Value) -> Option<rust_decimal::Decimal> {
+    let text = match value {
+        serde_json::Value::Number(n) => n.to_string(),
+        serde_json::Value::String(s) if !s.trim().is_empty() => s.trim().to_string(),
+        _ => return None,
+    };
+    text.parse::<rust_decimal::Decimal>()
+        .ok()
+        .or_else(|| rust_decimal::Decimal::from_scientific(&text).ok())
 }
 
+/// Runs the envelope extraction and date normalization of a fetch on a pasted
+/// response body, without touching the network, to reproduce parsing issues
 #[tauri::command]
-async fn cancel_operation() -> Result<(), String> {
-    let flag_guard = CANCELLATION_FLAG.lock().await;
-    if let Some(flag) = flag_guard.as_ref() {
-        flag.store(true, Ordering::Relaxed);
-    }
-    Ok(())
+fn parse_sample_response(
+    body: String,
+    raw_dates: Option<bool>,
+    scan_all_dates: Option<bool>,
+) -> Result<Vec<Transaction>, String> {
+    let date_mode = DateMode::new(raw_dates.unwrap_or(false), scan_all_dates.unwrap_or(false));
+    let json: serde_json::Value =
+        serde_json::from_str(body.trim()).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    let page = extract_page(json)?;
+    Ok(page
+        .results
+        .into_iter()
+        .filter_map(|result| match result {
+            serde_json::Value::Object(map) => Some(Transaction {
+                data: normalize_row(map, &[], date_mode),
+                source_division: None,
+            }),
+            _ => None,
+        })
+        .collect())
 }
 
 #[tauri::command]
-async fn logout() -> Result<(), String> {
-    use std::fs;
-    
-    let mut state_guard = get_app_state().await?;
-    let state = state_guard.as_mut().ok_or("State not initialized")?;
-    
-    // Clear tokens from memory
-    state.access_token = None;
-    state.refresh_token = None;
-    state.refresh_at = 0;
-    state.current_division = None;
-    
-    // Delete tokens file
-    let tokens_file = state.data_dir.join("tokens.json");
-    if tokens_file.exists() {
-        fs::remove_file(&tokens_file)
-            .map_err(|e| format!("Failed to delete tokens file: {}", e))?;
+async fn get_recent_errors() -> Result<Vec<ErrorRecord>, String> {
+    let state_guard = get_app_state().await?;
+    let state = state_guard.as_ref().ok_or("State not initialized")?;
+    Ok(state.recent_errors.iter().cloned().collect())
+}
+
+/// How long quitting waits for cancelled operations to wind down
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Cancels every running operation and gives them `SHUTDOWN_GRACE` to stop, so
+/// resumable fetches leave their partial file and descriptor on a page boundary.
+/// Then persists the tokens, which may have been refreshed since the last save.
+async fn shutdown() {
+    let running = {
+        let operations = OPERATIONS.lock().await;
+        for operation in operations.values() {
+            operation.cancel_flag.store(true, Ordering::Relaxed);
+        }
+        operations.len()
+    };
+    if running > 0 {
+        eprintln!("[SHUTDOWN] Cancelling {} running operation(s)", running);
+        let deadline = tokio::time::Instant::now() + SHUTDOWN_GRACE;
+        while !OPERATIONS.lock().await.is_empty() {
+            if tokio::time::Instant::now() >= deadline {
+                eprintln!("[SHUTDOWN] Operations still running after {:?}, exiting anyway", SHUTDOWN_GRACE);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    }
+
+    let Ok(state_guard) = tokio::time::timeout(SHUTDOWN_GRACE, APP_STATE.lock()).await else {
+        eprintln!("[SHUTDOWN] State is busy, tokens not saved");
+        return;
+    };
+    if let Some(state) = state_guard.as_ref() {
+        if state.access_token.is_some() {
+            if let Err(e) = state.save_tokens() {
+                eprintln!("[SHUTDOWN] Failed to save tokens: {}", e);
+            }
+        }
     }
-    
-    Ok(())
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -579,12 +7894,596 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             get_auth_url,
             authenticate_with_code,
+            extract_code_from_redirect,
+            start_login_flow,
+            cancel_login,
             get_divisions,
+            fetch_global,
+            stream_divisions,
+            get_division_access,
+            division_exists,
             get_transactions,
+            get_transactions_multi,
+            get_transactions_chunked,
+            fetch_entity,
+            get_sales_invoices,
+            get_bank_entries,
+            get_subscriptions,
+            get_vat_codes,
+            get_journals,
+            get_projects,
+            get_gl_classifications,
+            get_payment_conditions,
+            get_users,
+            get_cost_centers,
+            get_cost_units,
+            get_document_info,
+            download_document_attachment,
+            get_odata_metadata,
+            sync_endpoint,
+            start_watch,
+            stop_watch,
+            fetch_to_file,
+            distinct_field_values,
+            backup_division,
+            preview_request_url,
+            estimate_fetch_cost,
+            set_endpoint_page_size,
+            list_resumable_operations,
+            resume_persisted_fetch,
+            restart_transactions,
             is_authenticated,
+            unlock,
             logout,
-            cancel_operation
+            clear_division,
+            set_current_division,
+            cancel_operation,
+            pause_operation,
+            resume_operation,
+            get_recent_errors,
+            version_info,
+            app_registration_info,
+            verify_token_store,
+            fix_permissions,
+            last_refresh_info,
+            ping_api,
+            get_api_limits,
+            get_requests_in_flight,
+            connectivity_status,
+            export_settings,
+            import_settings,
+            query_cached_transactions,
+            get_transactions_offline,
+            list_cache_entries,
+            clear_cache_entry,
+            clear_all_cache,
+            parse_sample_response,
+            aggregate_transactions,
+            export_aggregates_csv,
+            diff_transactions,
+            summarize_divisions,
+            export_divisions_csv,
+            export_transactions_csv,
+            export_transactions_sqlite,
+            export_transactions_parquet,
+            collect_fields,
+            get_endpoint_fields,
+            fetch_and_export_csv,
+            import_transactions_csv,
+            save_query,
+            list_saved_queries,
+            delete_saved_query,
+            run_saved_query
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                tauri::async_runtime::block_on(shutdown());
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(api: &str) -> ApiSession {
+        ApiSession {
+            api: api.to_string(),
+            access_token: "token".to_string(),
+            client: reqwest::Client::new(),
+            cancel_check_interval: std::time::Duration::from_millis(100),
+            cancel_check_rows: 500,
+            encoding: BodyEncoding::Lossy,
+            retry_budget: 20,
+            validators: ValidatorCache::default(),
+        }
+    }
+
+    /// Answers the first request on a local port with the given status line,
+    /// content type and body; returns the base URL to pass as the API
+    async fn serve_once(status: &'static str, content_type: &'static str, body: &'static [u8]) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let head = format!(
+                "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                content_type,
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(body).await.unwrap();
+        });
+        format!("http://{}", address)
+    }
+
+    /// A file in the temp directory that is removed again when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!("exact_gui_{}_{}", std::process::id(), name)))
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn transaction(fields: serde_json::Value, source_division: Option<i32>) -> Transaction {
+        Transaction {
+            data: serde_json::from_value(fields).unwrap(),
+            source_division,
+        }
+    }
+
+    #[test]
+    fn next_page_links_stay_on_exact_hosts() {
+        let session = session("https://start.exactonline.nl/api");
+        assert_eq!(
+            session.url_for("/v1/1/bulk/Financial/TransactionLines").unwrap(),
+            "https://start.exactonline.nl/api/v1/1/bulk/Financial/TransactionLines"
+        );
+        for allowed in [
+            "https://start.exactonline.nl/api/v1/1/bulk/Financial/TransactionLines?$skiptoken=guid'1'",
+            "https://start2.exactonline.nl/api/v1/1/bulk/Financial/TransactionLines?$skiptoken=guid'1'",
+        ] {
+            assert_eq!(session.url_for(allowed).unwrap(), allowed);
+        }
+        for refused in [
+            "http://start.exactonline.nl/api/v1/1/next",
+            "https://attacker.example/api/v1/1/next",
+            "https://start.exactonline.nl.attacker.example/api/v1/1/next",
+            "https://start.exactonline.be/api/v1/1/next",
+            "https://start.exactonline.nl:8443/api/v1/1/next",
+        ] {
+            assert!(session.url_for(refused).is_err(), "{} was followed", refused);
+        }
+    }
+
+    #[test]
+    fn empty_fetch_completes_successfully() {
+        let payload = completion_payload("op-1", 0, 0, 0);
+        assert_eq!(payload["operation_id"], "op-1");
+        assert_eq!(payload["success"], true);
+        assert_eq!(payload["matched"], 0);
+        assert_eq!(payload["reduced_pages"], 0);
+    }
+
+    #[test]
+    fn gateway_api_only_follows_its_own_origin() {
+        let session = session("https://gateway.example.com/exact");
+        assert!(session.url_for("https://gateway.example.com/exact/v1/1/next").is_ok());
+        assert!(session.url_for("https://start.exactonline.nl/api/v1/1/next").is_err());
+    }
+
+    #[test]
+    fn parse_count_accepts_every_count_shape() {
+        use serde_json::json;
+        assert_eq!(parse_count(&json!(42)), Some(42));
+        assert_eq!(parse_count(&json!({ "d": 42 })), Some(42));
+        assert_eq!(parse_count(&json!({ "d": "42" })), Some(42));
+        assert_eq!(parse_count(&json!({ "d": { "__count": "42" } })), Some(42));
+        assert_eq!(parse_count(&json!(" 42 ")), Some(42));
+        assert_eq!(parse_count(&json!("many")), None);
+        assert_eq!(parse_count(&json!({ "d": [] })), None);
+        assert_eq!(parse_count(&json!(null)), None);
+    }
+
+    #[tokio::test]
+    async fn csv_export_reads_back_unchanged() {
+        use serde_json::json;
+        let file = TempFile::new("roundtrip.csv");
+        let transactions = vec![
+            transaction(
+                json!({
+                    "AmountDC": 1234.5,
+                    "EntryNumber": 20240001,
+                    "FinancialYear": 2024,
+                    "Description": "Rent, January",
+                    "Date": "2024-01-31T00:00:00+00:00"
+                }),
+                Some(101),
+            ),
+            // Absent fields are written as empty cells
+            transaction(json!({ "AmountDC": -0.01, "EntryNumber": 20240002 }), Some(202)),
+        ];
+        assert_eq!(
+            export_transactions_csv(transactions.clone(), file.path().to_string(), None).await,
+            Ok(2)
+        );
+
+        let imported = read_transactions_csv(file.path()).unwrap();
+        assert_eq!(imported.len(), transactions.len());
+        for (original, imported) in transactions.iter().zip(&imported) {
+            assert_eq!(imported.data, original.data);
+            assert_eq!(imported.source_division, original.source_division);
+        }
+    }
+
+    #[tokio::test]
+    async fn aggregate_sums_survive_csv_export() {
+        use serde_json::json;
+        let file = TempFile::new("aggregates.csv");
+        let transactions = vec![
+            transaction(json!({ "GLAccountCode": "8000", "AmountDC": 0.1 }), None),
+            transaction(json!({ "GLAccountCode": "8000", "AmountDC": 0.2 }), None),
+            transaction(json!({ "GLAccountCode": "4000", "AmountDC": "-12.345" }), None),
+        ];
+        let rows = aggregate_transactions(transactions, vec!["GLAccountCode".to_string()], vec!["AmountDC".to_string()])
+            .unwrap();
+        assert_eq!(export_aggregates_csv(rows, file.path().to_string()).await, Ok(2));
+
+        let mut reader = csv::Reader::from_path(file.path()).unwrap();
+        assert_eq!(reader.headers().unwrap(), vec!["GLAccountCode", "AmountDC", "count"]);
+        let records: Vec<Vec<String>> = reader
+            .records()
+            .map(|record| record.unwrap().iter().map(String::from).collect())
+            .collect();
+        assert_eq!(records, vec![vec!["4000", "-12.345", "1"], vec!["8000", "0.3", "2"]]);
+    }
+
+    #[tokio::test]
+    async fn parquet_export_reads_back_typed() {
+        use arrow::array::{Array, Float64Array, Int64Array, StringArray, TimestampMillisecondArray};
+        use arrow::datatypes::{DataType, TimeUnit};
+        use serde_json::json;
+
+        let file = TempFile::new("roundtrip.parquet");
+        let transactions = vec![
+            transaction(
+                json!({
+                    "AmountDC": 99.95,
+                    "EntryNumber": 20240001,
+                    "Description": "Office chairs",
+                    "Date": "2024-01-31T00:00:00+00:00"
+                }),
+                Some(101),
+            ),
+            // Missing fields and raw Exact dates
+            transaction(json!({ "AmountDC": "-5", "Date": "/Date(1706659200000)/" }), None),
+        ];
+        assert_eq!(
+            export_transactions_parquet(transactions, file.path().to_string()).await,
+            Ok(2)
+        );
+
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReader::try_new(
+            fs::File::open(file.path()).unwrap(),
+            1024,
+        )
+        .unwrap();
+        let batches: Vec<_> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+
+        let schema = batch.schema();
+        let data_type = |name: &str| schema.field_with_name(name).unwrap().data_type().clone();
+        assert_eq!(data_type("AmountDC"), DataType::Float64);
+        assert_eq!(data_type("Date"), DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())));
+        assert_eq!(data_type("EntryNumber"), DataType::Int64);
+        assert_eq!(data_type(SOURCE_DIVISION_COLUMN), DataType::Int64);
+        assert_eq!(data_type("Description"), DataType::Utf8);
+
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let amounts = column("AmountDC");
+        let amounts = amounts.as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!((amounts.value(0), amounts.value(1)), (99.95, -5.0));
+        let dates = column("Date");
+        let dates = dates.as_any().downcast_ref::<TimestampMillisecondArray>().unwrap();
+        assert_eq!((dates.value(0), dates.value(1)), (1706659200000, 1706659200000));
+        let entries = column("EntryNumber");
+        let entries = entries.as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(entries.value(0), 20240001);
+        assert!(entries.is_null(1));
+        let descriptions = column("Description");
+        let descriptions = descriptions.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(descriptions.value(0), "Office chairs");
+        assert!(descriptions.is_null(1));
+        let divisions = column(SOURCE_DIVISION_COLUMN);
+        let divisions = divisions.as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(divisions.value(0), 101);
+        assert!(divisions.is_null(1));
+    }
+
+    #[test]
+    fn parquet_export_rejects_mistyped_values() {
+        let file = TempFile::new("mistyped.parquet");
+        let transactions = vec![transaction(serde_json::json!({ "AmountDC": "n/a" }), None)];
+        let error = write_parquet(&transactions, file.path()).unwrap_err();
+        assert!(error.contains("AmountDC in row 1"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn division_csv_quotes_names_with_commas() {
+        let file = TempFile::new("divisions.csv");
+        let divisions = vec![Division {
+            Code: 123456,
+            CustomerName: "Smit, Jansen & Co".to_string(),
+            Description: "Holding".to_string(),
+            Customer: None,
+            CustomerCode: Some("100".to_string()),
+        }];
+        export_divisions_csv(divisions, file.path().to_string()).await.unwrap();
+
+        let content = fs::read_to_string(file.path()).unwrap();
+        assert_eq!(
+            content,
+            "Code,CustomerCode,CustomerName,Description\n123456,100,\"Smit, Jansen & Co\",Holding\n"
+        );
+        let mut reader = csv::Reader::from_path(file.path()).unwrap();
+        let record = reader.records().next().unwrap().unwrap();
+        assert_eq!(&record[2], "Smit, Jansen & Co");
+    }
+
+    #[test]
+    fn divisions_sort_ignoring_case_and_accents() {
+        let division = |name: &str| Division {
+            Code: 1,
+            CustomerName: name.to_string(),
+            Description: String::new(),
+            Customer: None,
+            CustomerCode: None,
+        };
+        let names = |divisions: &[Division]| divisions.iter().map(|d| d.CustomerName.clone()).collect::<Vec<_>>();
+        let mut divisions: Vec<Division> = ["zeeman", "Ëlla", "Bakker", "ella", "Ärzte", "andersen"]
+            .into_iter()
+            .map(division)
+            .collect();
+
+        sort_divisions(&mut divisions, "");
+        assert_eq!(names(&divisions), ["andersen", "Ärzte", "Bakker", "ella", "Ëlla", "zeeman"]);
+
+        sort_divisions(&mut divisions, "binary");
+        assert_eq!(names(&divisions), ["Bakker", "andersen", "ella", "zeeman", "Ärzte", "Ëlla"]);
+
+        assert_eq!(collation_key("ÉÇÖ Łódź", ""), "eco łodz");
+    }
+
+    #[tokio::test]
+    async fn forbidden_division_is_reported_as_access_lost() {
+        let api = serve_once(
+            "403 Forbidden",
+            "application/json",
+            br#"{"error":{"code":"","message":{"lang":"","value":"Forbidden"}}}"#,
+        )
+        .await;
+        let error = session(&api)
+            .get("/v1/123456/financial/GLAccounts?$top=1")
+            .await
+            .unwrap_err();
+        assert_eq!(error, format!("{} 123456", DIVISION_ACCESS_LOST_ERROR));
+        assert_eq!(error_kind(&error), "division_access");
+        assert_eq!(lost_division(&error), Some(123456));
+    }
+
+    #[tokio::test]
+    async fn forbidden_global_request_is_a_plain_api_error() {
+        let api = serve_once("403 Forbidden", "application/json", br#"{"error":"Forbidden"}"#).await;
+        let error = session(&api).get("/v1/current/Me").await.unwrap_err();
+        assert!(error.starts_with("API error (403"), "{}", error);
+        assert_eq!(lost_division(&error), None);
+    }
+
+    #[test]
+    fn fields_are_classified_by_kind() {
+        for field in ["AmountDC", "AmountFC", "AmountVATFC", "Quantity", "VATPercentage"] {
+            assert_eq!(field_kind(field), FieldKind::Amount, "{}", field);
+        }
+        for field in ["Date", "DueDate", "Modified", "Created"] {
+            assert_eq!(field_kind(field), FieldKind::Date, "{}", field);
+        }
+        for field in ["Description", "GLAccountCode", "EntryNumber", "FinancialYear", "Unknown"] {
+            assert_eq!(field_kind(field), FieldKind::Text, "{}", field);
+        }
+        // Counters are text to the normalizer but numbers to the exports
+        for field in ["EntryNumber", "FinancialYear", "FinancialPeriod", "Status"] {
+            assert!(INTEGER_FIELDS.contains(&field), "{}", field);
+        }
+        assert!(!INTEGER_FIELDS.contains(&"AmountDC"));
+        assert!(AMOUNT_FIELDS.iter().all(|field| !DATE_FIELDS.contains(field)));
+    }
+
+    #[test]
+    fn non_utf8_bodies_decode_per_encoding() {
+        // "Café" as Latin-1, as re-encoding proxies send it
+        let body = b"{\"Description\":\"Caf\xe9\"}";
+        assert_eq!(
+            decode_body(body, BodyEncoding::Lossy, "/v1/1/x").unwrap(),
+            "{\"Description\":\"Caf\u{fffd}\"}"
+        );
+        let error = decode_body(body, BodyEncoding::Strict, "/v1/1/x").unwrap_err();
+        assert!(error.starts_with("Failed to read response: invalid UTF-8 from /v1/1/x"), "{}", error);
+        assert_eq!(error_kind(&error), "network");
+        assert_eq!(
+            decode_body(body, BodyEncoding::Latin1, "/v1/1/x").unwrap(),
+            "{\"Description\":\"Café\"}"
+        );
+        assert_eq!(decode_body("Café".as_bytes(), BodyEncoding::Strict, "/v1/1/x").unwrap(), "Café");
+    }
+
+    #[tokio::test]
+    async fn lossy_session_reads_non_utf8_json() {
+        let api = serve_once("200 OK", "application/json", b"{\"d\":{\"results\":[{\"Name\":\"Caf\xe9\"}]}}").await;
+        let json = session(&api).get("/v1/1/crm/Accounts").await.unwrap();
+        assert_eq!(json["d"]["results"][0]["Name"], "Caf\u{fffd}");
+    }
+
+    #[test]
+    fn refresh_at_keeps_a_margin_before_expiry() {
+        use serde_json::json;
+        let now = 1_700_000_000;
+        assert_eq!(refresh_at_for(&json!({}), now), now + 570);
+        assert_eq!(refresh_at_for(&json!({ "expires_in": "600" }), now), now + 570);
+        assert_eq!(refresh_at_for(&json!({ "expires_in": 600 }), now), now + 570);
+        assert_eq!(refresh_at_for(&json!({ "expires_in": "soon" }), now), now + 570);
+        assert_eq!(refresh_at_for(&json!({ "expires_in": 0 }), now), now + 570);
+        // Lifetimes shorter than the margin still wait half of it
+        assert_eq!(refresh_at_for(&json!({ "expires_in": 40 }), now), now + 20);
+        assert_eq!(refresh_at_for(&json!({ "expires_in": "20" }), now), now + 10);
+    }
+
+    #[tokio::test]
+    async fn fetch_without_tokens_fails_before_any_request() {
+        let data_dir = std::env::temp_dir().join(format!("exact_gui_{}_data", std::process::id()));
+        let mut state = AppState::with_data_dir(data_dir.clone()).unwrap();
+        // Nothing listens here, so a request would fail with a network error instead
+        state.api = "http://127.0.0.1:9".to_string();
+
+        let error = state.refresh_token().await.unwrap_err();
+        assert_eq!(error, NOT_AUTHENTICATED_ERROR);
+        assert_eq!(error_kind(&error), "auth");
+        assert_eq!(state.session().err().as_deref(), Some(NOT_AUTHENTICATED_ERROR));
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn filters_are_trimmed_and_smart_quotes_replaced() {
+        assert_eq!(normalize_filter("  FinancialYear gt 2022 \n", false), "FinancialYear gt 2022");
+        assert_eq!(
+            normalize_filter("JournalCode eq \u{2018}70\u{2019}", true),
+            "JournalCode eq '70'"
+        );
+        assert_eq!(
+            normalize_filter("Description eq \u{201C}x\u{201D}", true),
+            "Description eq \"x\""
+        );
+        // Left alone unless asked for, e.g. a quote that is part of the value
+        assert_eq!(
+            normalize_filter("Description eq 'Jansen\u{2019}s'", false),
+            "Description eq 'Jansen\u{2019}s'"
+        );
+
+        let request = FetchRequest {
+            filter: Some(" JournalCode eq \u{2018}70\u{2019} ".to_string()),
+            normalize_quotes: true,
+            ..FetchRequest::transaction_lines(1, None)
+        };
+        assert_eq!(filter_param(&request), "&$filter=JournalCode%20eq%20%2770%27");
+        let blank = FetchRequest::transaction_lines(1, Some("   ".to_string()));
+        assert_eq!(filter_param(&blank), "");
+    }
+
+    #[test]
+    fn default_select_depends_on_the_endpoint() {
+        let lines = collection_path(&FetchRequest::transaction_lines(1, None)).unwrap();
+        assert!(lines.starts_with(&format!("/v1/1/{}?$select={}", TRANSACTION_LINES_ENDPOINT, TRANSACTION_FIELDS)));
+
+        let cost_centers = FetchRequest {
+            division: 1,
+            endpoint: "hrm/Costcenters".to_string(),
+            ..Default::default()
+        };
+        let path = collection_path(&cost_centers).unwrap();
+        assert!(path.starts_with("/v1/1/hrm/Costcenters?$select=Active,Code,"), "{}", path);
+        assert!(!path.contains("GLAccount"), "{}", path);
+
+        let unknown = FetchRequest {
+            division: 1,
+            endpoint: "crm/Unknown".to_string(),
+            ..Default::default()
+        };
+        assert!(collection_path(&unknown).unwrap_err().contains("explicit select"));
+    }
+
+    #[test]
+    fn only_an_explicit_select_is_validated() {
+        let request = |select: &[&str]| FetchRequest {
+            division: 1,
+            endpoint: "hrm/Costunits".to_string(),
+            select: Some(select.iter().map(|f| f.to_string()).collect()),
+            ..Default::default()
+        };
+        assert!(collection_path(&request(&["Code", "Description"])).is_ok());
+        assert_eq!(
+            collection_path(&request(&["Code", "GLAccount", "Amount"])).unwrap_err(),
+            "Cannot select GLAccount, Amount on hrm/Costunits"
+        );
+        // An empty select falls back to the registry instead of failing validation
+        assert!(collection_path(&request(&[])).unwrap().contains("$select=Account,AccountCode,"));
+    }
+
+    #[test]
+    fn offline_cache_round_trips_per_division() {
+        let dir = std::env::temp_dir().join(format!("exact_gui_{}_offline", std::process::id()));
+        assert!(load_offline(&dir, 1).unwrap().is_none());
+
+        let fetched_at = chrono::Utc::now();
+        let row = transaction(serde_json::json!({"EntryNumber": 7, "Description": "Rent"}), Some(2));
+        save_offline(&dir, 2, fetched_at, &[&row]).unwrap();
+        let (header, rows) = load_offline(&dir, 2).unwrap().unwrap();
+        assert_eq!(header.fetched_at, fetched_at);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].data["Description"], "Rent");
+        assert_eq!(rows[0].source_division, Some(2));
+        assert!(load_offline(&dir, 1).unwrap().is_none());
+
+        // A newer fetch replaces the division's rows
+        save_offline(&dir, 2, fetched_at, &[]).unwrap();
+        assert!(load_offline(&dir, 2).unwrap().unwrap().1.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn cache_entries_describe_and_clear_files() {
+        let data_dir = std::env::temp_dir().join(format!("exact_gui_{}_cache", std::process::id()));
+        let metadata = data_dir.join("metadata");
+        fs::create_dir_all(&metadata).unwrap();
+        fs::write(metadata.join("12_financialtransaction.xml"), "<edmx/>").unwrap();
+        let row = transaction(serde_json::json!({"EntryNumber": 1}), None);
+        save_offline(&data_dir.join(OFFLINE_DIR), 12, chrono::Utc::now(), &[&row]).unwrap();
+
+        let entries = cache_entries(&data_dir);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].cache, "metadata");
+        assert_eq!(entries[0].division, Some(12));
+        assert_eq!(entries[0].endpoint.as_deref(), Some("financialtransaction"));
+        assert_eq!(entries[0].size_bytes, 7);
+        assert!(entries[0].fetched_at.is_some());
+        assert_eq!(entries[1].name, "12.ndjson");
+        assert_eq!(entries[1].endpoint.as_deref(), Some(TRANSACTION_LINES_ENDPOINT));
+
+        remove_cache_entry(&data_dir, &entries[0]).await.unwrap();
+        assert_eq!(cache_entries(&data_dir).len(), 1);
+        let _ = fs::remove_dir_all(&data_dir);
+    }
 }